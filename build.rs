@@ -0,0 +1,131 @@
+//! Embeds the kernel symbol table used to symbolize backtraces, and packs the `initrd`
+//! directory into an archive that the kernel unpacks into its filesystem at boot.
+//!
+//! A kernel can't contain its own final symbol addresses on the first build, so symbolizing is
+//! a two-step process: build once, dump the symbols with `nm`, then build again with
+//! `SPRINKLES_SYMBOLS` pointing at the dump. The table is always padded to `SYMBOL_TABLE_SIZE`,
+//! so embedding it doesn't move any code and the addresses from the first build stay valid.
+
+use std::{env, fs, path::{Path, PathBuf}};
+
+/// Must match `backtrace::SYMBOL_TABLE_SIZE`
+const SYMBOL_TABLE_SIZE: usize = 512 * 1024;
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    symbols(&out_dir);
+    initrd(&out_dir);
+}
+
+fn symbols(out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed=SPRINKLES_SYMBOLS");
+
+    let mut table = Vec::new();
+
+    if let Ok(path) = env::var("SPRINKLES_SYMBOLS") {
+        println!("cargo:rerun-if-changed={path}");
+
+        let listing = fs::read_to_string(&path).expect("Failed to read SPRINKLES_SYMBOLS");
+        table = encode(&listing);
+    }
+
+    if table.len() > SYMBOL_TABLE_SIZE {
+        println!("cargo:warning=Symbol table is too large; backtraces will not be symbolized");
+        table.clear();
+    }
+
+    table.resize(SYMBOL_TABLE_SIZE, 0);
+
+    fs::write(out_dir.join("symbols.bin"), table).unwrap();
+}
+
+/// Packs every file under `initrd/` into a list of `(path length: u16, path, contents length:
+/// u32, contents)` records, terminated by a zero path length. Paths are relative to `initrd/`.
+fn initrd(out_dir: &Path) {
+    println!("cargo:rerun-if-changed=initrd");
+
+    let mut files = Vec::new();
+    collect_files(Path::new("initrd"), &mut files);
+    files.sort();
+
+    let mut archive = Vec::new();
+
+    for path in files {
+        let name = path
+            .strip_prefix("initrd")
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_str().expect("initrd paths must be UTF-8"))
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = fs::read(&path).unwrap();
+
+        archive.extend((name.len() as u16).to_le_bytes());
+        archive.extend(name.as_bytes());
+        archive.extend((contents.len() as u32).to_le_bytes());
+        archive.extend(contents);
+    }
+
+    archive.extend(0u16.to_le_bytes());
+
+    fs::write(out_dir.join("initrd.bin"), archive).unwrap();
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// Turns the output of `nm -n -C` into a list of `(address: u64, name length: u16, name)`
+/// records, sorted by address and terminated by a zero address.
+fn encode(listing: &str) -> Vec<u8> {
+    let mut symbols: Vec<(u64, &str)> = listing
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let kind = parts.next()?;
+            let name = parts.next()?;
+
+            // Only code symbols are useful for backtraces
+            if !matches!(kind, "T" | "t" | "W" | "w") {
+                return None;
+            }
+
+            // Rust's legacy mangling leaves a `::h<hash>` suffix after demangling
+            let name = match name.rsplit_once("::h") {
+                Some((path, hash)) if hash.len() == 16 => path,
+                _ => name,
+            };
+
+            Some((addr, name))
+        })
+        .collect();
+
+    symbols.sort_by_key(|(addr, _)| *addr);
+
+    let mut table = Vec::new();
+
+    for (addr, name) in symbols {
+        let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+
+        table.extend(addr.to_le_bytes());
+        table.extend((name.len() as u16).to_le_bytes());
+        table.extend(name);
+    }
+
+    table.extend(0u64.to_le_bytes());
+    table
+}
@@ -0,0 +1,389 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::slice;
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+#[derive(Clone, Copy, Hash, PartialEq, PartialOrd, Ord, Eq, Debug)]
+pub enum AcpiError {
+    /// No Root System Description Pointer in the BIOS areas
+    RsdpNotFound,
+    /// A table (identified by its signature) failed its checksum
+    InvalidChecksum([u8; 4]),
+    /// A table (identified by its signature) claims to be shorter than its own header
+    InvalidLength([u8; 4]),
+}
+
+/// The size of the header that every System Description Table starts with
+const SDT_HEADER_SIZE: usize = 36;
+
+/// Every System Description Table listed by the RSDT/XSDT, as raw bytes (including the header)
+pub struct Acpi {
+    pub revision: u8,
+    tables: Vec<&'static [u8]>,
+}
+
+static ACPI: OnceCell<Acpi> = OnceCell::uninit();
+
+/// Locates the RSDP and collects the tables listed in the RSDT (or XSDT, on ACPI 2.0+).
+/// Needs the heap and the physical memory mapping.
+pub fn init() -> Result<(), AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+
+    let revision = rsdp[15];
+    let (root, entry_size) = if revision >= 2 {
+        (PhysAddr::new(u64_at(rsdp, 24)), 8)
+    } else {
+        (PhysAddr::new(u32_at(rsdp, 16) as u64), 4)
+    };
+
+    let root = sdt_at(root)?;
+    let entries = &root[SDT_HEADER_SIZE..];
+
+    let mut tables = Vec::new();
+
+    for entry in entries.chunks_exact(entry_size) {
+        let addr = match entry_size {
+            8 => u64_at(entry, 0),
+            _ => u32_at(entry, 0) as u64,
+        };
+
+        tables.push(sdt_at(PhysAddr::new(addr))?);
+    }
+
+    ACPI.init_once(|| Acpi { revision, tables });
+
+    Ok(())
+}
+
+/// Returns the raw bytes of the first table with the given signature, such as `b"APIC"`.
+pub fn table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    ACPI.try_get()
+        .ok()?
+        .tables
+        .iter()
+        .find(|table| &table[..4] == signature)
+        .copied()
+}
+
+/// Searches the first KiB of the Extended BIOS Data Area, then the BIOS ROM, for the RSDP.
+fn find_rsdp() -> Option<&'static [u8]> {
+    let ebda_segment = unsafe { *memory::phys_to_virt(PhysAddr::new(0x40e)).as_ptr::<u16>() };
+    let ebda = (ebda_segment as u64) << 4;
+
+    let search_areas = [(ebda, ebda + 1024), (0xe0000, 0x100000)];
+
+    for (start, end) in search_areas {
+        if start == 0 {
+            continue;
+        }
+
+        for addr in (start..end).step_by(16) {
+            let candidate = phys_slice(PhysAddr::new(addr), 20);
+
+            if &candidate[..8] != b"RSD PTR " || !checksum_ok(candidate) {
+                continue;
+            }
+
+            // ACPI 2.0+ extends the RSDP with the XSDT address, under its own checksum
+            if candidate[15] >= 2 {
+                let length = u32_at(phys_slice(PhysAddr::new(addr), 24), 20) as usize;
+                let extended = phys_slice(PhysAddr::new(addr), length);
+
+                if checksum_ok(extended) {
+                    return Some(extended);
+                }
+            }
+
+            return Some(candidate);
+        }
+    }
+
+    None
+}
+
+/// Returns a whole System Description Table after validating its length and checksum.
+fn sdt_at(addr: PhysAddr) -> Result<&'static [u8], AcpiError> {
+    let header = phys_slice(addr, SDT_HEADER_SIZE);
+    let signature = header[..4].try_into().unwrap();
+    let length = u32_at(header, 4) as usize;
+
+    if length < SDT_HEADER_SIZE {
+        return Err(AcpiError::InvalidLength(signature));
+    }
+
+    let table = phys_slice(addr, length);
+
+    if !checksum_ok(table) {
+        return Err(AcpiError::InvalidChecksum(signature));
+    }
+
+    Ok(table)
+}
+
+fn phys_slice(addr: PhysAddr, len: usize) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(memory::phys_to_virt(addr).as_ptr(), len) }
+}
+
+/// ACPI structures are valid if all of their bytes add up to 0
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A processor's local APIC, from the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// Whether the processor is enabled or can be brought online
+    pub usable: bool,
+}
+
+/// An I/O APIC, from the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first Global System Interrupt that this I/O APIC handles
+    pub gsi_base: u32,
+}
+
+/// A remapping of an ISA IRQ onto a different Global System Interrupt, from the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// The Multiple APIC Description Table
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// Whether the machine also has legacy 8259 PICs that need to be disabled
+    pub legacy_pics: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+impl Madt {
+    /// Returns the Global System Interrupt that the ISA `irq` is wired to, and whether it's
+    /// active low and level triggered. ISA IRQs are identity mapped, active high and
+    /// edge triggered unless the MADT says otherwise.
+    pub fn isa_irq(&self, irq: u8) -> InterruptOverride {
+        self.overrides
+            .iter()
+            .find(|o| o.irq == irq)
+            .copied()
+            .unwrap_or(InterruptOverride {
+                irq,
+                gsi: irq as u32,
+                active_low: false,
+                level_triggered: false,
+            })
+    }
+}
+
+/// Parses the MADT, if the firmware provides one.
+pub fn madt() -> Option<Madt> {
+    let table = table(b"APIC")?;
+
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::new(u32_at(table, 36) as u64),
+        legacy_pics: u32_at(table, 40) & 1 != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut entries = &table[44..];
+
+    while let [kind, length, ..] = *entries {
+        let length = length as usize;
+
+        if length < 2 || length > entries.len() {
+            break;
+        }
+
+        let entry = &entries[..length];
+
+        match kind {
+            0 => madt.processors.push(Processor {
+                processor_id: entry[2],
+                apic_id: entry[3],
+                usable: u32_at(entry, 4) & 0b11 != 0,
+            }),
+            1 => madt.io_apics.push(IoApicInfo {
+                id: entry[2],
+                address: PhysAddr::new(u32_at(entry, 4) as u64),
+                gsi_base: u32_at(entry, 8),
+            }),
+            2 => {
+                let flags = u16_at(entry, 8);
+
+                madt.overrides.push(InterruptOverride {
+                    irq: entry[3],
+                    gsi: u32_at(entry, 4),
+                    active_low: flags & 0b11 == 0b11,
+                    level_triggered: (flags >> 2) & 0b11 == 0b11,
+                })
+            }
+            5 => madt.local_apic_address = PhysAddr::new(u64_at(entry, 4)),
+            _ => {}
+        }
+
+        entries = &entries[length..];
+    }
+
+    Some(madt)
+}
+
+/// Where a register lives, from a Generic Address Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenericAddress {
+    Memory(PhysAddr),
+    Io(u16),
+}
+
+impl GenericAddress {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let address = u64_at(bytes, 4);
+
+        match bytes[0] {
+            _ if address == 0 => None,
+            0 => Some(GenericAddress::Memory(PhysAddr::new(address))),
+            1 => Some(GenericAddress::Io(address as u16)),
+            _ => None,
+        }
+    }
+}
+
+/// The parts of the Fixed ACPI Description Table that the kernel uses for power management
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub dsdt: PhysAddr,
+    pub smi_command_port: u16,
+    pub acpi_enable: u8,
+    pub pm1a_control_block: u16,
+    pub pm1b_control_block: u16,
+    /// Whether the machine has a PS/2 controller (IAPC_BOOT_ARCH bit 1)
+    pub has_8042: bool,
+    /// The register that resets the machine when `reset_value` is written to it
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+/// Parses the FADT, if the firmware provides one.
+pub fn fadt() -> Option<Fadt> {
+    let table = table(b"FACP")?;
+
+    // Older (ACPI 1.0) FADTs end before the boot architecture flags and reset register
+    let revision_2 = table.len() >= 129;
+
+    let mut dsdt = u32_at(table, 40) as u64;
+    if table.len() >= 148 && u64_at(table, 140) != 0 {
+        dsdt = u64_at(table, 140);
+    }
+
+    let reset_supported = revision_2 && u32_at(table, 112) & (1 << 10) != 0;
+
+    Some(Fadt {
+        dsdt: PhysAddr::new(dsdt),
+        smi_command_port: u32_at(table, 48) as u16,
+        acpi_enable: table[52],
+        pm1a_control_block: u32_at(table, 64) as u16,
+        pm1b_control_block: u32_at(table, 68) as u16,
+        // Without the flags, assume the 8042 is there like it is on every PC
+        has_8042: !revision_2 || u16_at(table, 109) & 0b10 != 0,
+        reset_register: reset_supported
+            .then(|| GenericAddress::parse(&table[116..128]))
+            .flatten(),
+        reset_value: if revision_2 { table[128] } else { 0 },
+    })
+}
+
+impl Fadt {
+    /// Finds the SLP_TYPa and SLP_TYPb values for the S5 (soft off) sleep state.
+    ///
+    /// They live in the `\_S5` package in the DSDT, which is AML bytecode. Rather than
+    /// interpreting AML, this looks for the package's encoding directly:
+    /// `NameOp "_S5_" PackageOp PkgLength NumElements [BytePrefix] SLP_TYPa [BytePrefix] SLP_TYPb`
+    pub fn s5_sleep_types(&self) -> Option<(u8, u8)> {
+        const NAME_OP: u8 = 0x08;
+        const PACKAGE_OP: u8 = 0x12;
+        const BYTE_PREFIX: u8 = 0x0a;
+
+        let dsdt = sdt_at(self.dsdt).ok()?;
+        let aml = &dsdt[SDT_HEADER_SIZE..];
+
+        let position = aml.windows(4).position(|window| window == b"_S5_")?;
+
+        let named = match position {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[position - 1] == NAME_OP || (aml[position - 2] == NAME_OP && aml[position - 1] == b'\\'),
+        };
+
+        if !named || *aml.get(position + 4)? != PACKAGE_OP {
+            return None;
+        }
+
+        // The top two bits of the first PkgLength byte say how many more bytes follow
+        let mut cursor = position + 5;
+        cursor += ((*aml.get(cursor)? >> 6) + 1) as usize;
+        // NumElements
+        cursor += 1;
+
+        let mut next_value = || {
+            if *aml.get(cursor)? == BYTE_PREFIX {
+                cursor += 1;
+            }
+
+            let value = *aml.get(cursor)?;
+            cursor += 1;
+            Some(value)
+        };
+
+        Some((next_value()?, next_value()?))
+    }
+}
+
+/// The High Precision Event Timer, from the HPET table
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub base_address: PhysAddr,
+    pub hpet_number: u8,
+    /// The minimum number of ticks that periodic timers can be set to
+    pub minimum_tick: u16,
+}
+
+/// Parses the HPET table, if the firmware provides one. `time` measures the timestamp counter
+/// against it.
+pub fn hpet() -> Option<Hpet> {
+    let table = table(b"HPET")?;
+
+    let GenericAddress::Memory(base_address) = GenericAddress::parse(&table[40..52])? else {
+        return None;
+    };
+
+    Some(Hpet {
+        base_address,
+        hpet_number: table[52],
+        minimum_tick: u16_at(table, 53),
+    })
+}
@@ -5,10 +5,37 @@ use x86_64::{
     VirtAddr,
 };
 
+use core::alloc::{GlobalAlloc, Layout};
+
 use linked_list_allocator::LockedHeap;
 
+use crate::config;
+use crate::vt;
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: InterruptSafeHeap = InterruptSafeHeap(LockedHeap::empty());
+
+/// A heap that disables interrupts while it's locked. Interrupt handlers allocate (waking a task
+/// can grow its CPU's ready queue), and would deadlock on a lock held by the code they interrupted.
+struct InterruptSafeHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for InterruptSafeHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        x86_64::instructions::interrupts::without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        x86_64::instructions::interrupts::without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
+/// The number of bytes of heap in use, and the size of the heap
+pub fn heap_usage() -> (usize, usize) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        (heap.used(), heap.size())
+    })
+}
 
 //pub struct Dummy;
 
@@ -18,15 +45,20 @@ fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
 }
 
 const HEAP_START: usize = 0x_4444_4444_0000;
-const HEAP_SIZE: usize = 100 * 1024;
+/// Enough for every terminal to fill its scrollback. `heap_size` in `boot.cfg` overrides it.
+pub const DEFAULT_HEAP_SIZE: usize = 4 * 1024 * 1024;
+/// Room for every terminal's scrollback, and for the rest of the kernel
+pub const MIN_HEAP_SIZE: usize = vt::SCROLLBACK_SIZE + 1024 * 1024;
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    let heap_size = config::get().heap_size;
+
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_end = heap_start + heap_size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -40,7 +72,7 @@ pub fn init_heap(
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
-    unsafe { ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE) }
+    unsafe { ALLOCATOR.0.lock().init(HEAP_START, heap_size) }
 
     Ok(())
 }
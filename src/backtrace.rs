@@ -0,0 +1,176 @@
+use core::{arch::asm, fmt, hint, str};
+
+use spin::Mutex;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use crate::percpu;
+use crate::stack;
+
+/// The size of the symbol table embedded by `build.rs`
+pub const SYMBOL_TABLE_SIZE: usize = 512 * 1024;
+
+/// The maximum number of frames that a backtrace will record
+const MAX_FRAMES: usize = 32;
+
+/// Records of `(address: u64, name length: u16, name)`, sorted by address and terminated by a
+/// zero address. Entirely zeroes if the kernel was built without `SPRINKLES_SYMBOLS`.
+static SYMBOL_TABLE: [u8; SYMBOL_TABLE_SIZE] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin"));
+
+/// The backtrace of an exception that's about to panic, and the CPU it happened on. The panic
+/// handler shows it instead of its own, which would only go as far back as the exception handler.
+static EXCEPTION_BACKTRACE: Mutex<Option<(usize, Backtrace)>> = Mutex::new(None);
+
+/// The return addresses of a call stack, innermost first.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+    /// The first frame is the instruction that faulted rather than a return address
+    from_exception: bool,
+}
+
+impl Backtrace {
+    /// Walks the call stack of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+        Self::from_frame_pointer(rbp)
+    }
+
+    /// Walks the call stack that an exception interrupted, starting with the instruction that
+    /// caused it. `rbp` is the interrupted code's frame pointer, which the handler saved.
+    pub fn from_exception(stack_frame: &InterruptStackFrame, rbp: u64) -> Self {
+        let mut backtrace = Self::from_frame_pointer(rbp);
+
+        backtrace.frames.copy_within(..MAX_FRAMES - 1, 1);
+        backtrace.frames[0] = stack_frame.instruction_pointer.as_u64();
+        backtrace.len = (backtrace.len + 1).min(MAX_FRAMES);
+        backtrace.from_exception = true;
+
+        backtrace
+    }
+
+    /// Walks the chain of saved frame pointers starting at `rbp`. The kernel is built with
+    /// `force-frame-pointers`, so every frame starts with the caller's `rbp` followed by the
+    /// return address. The walk stops as soon as a frame pointer leaves the known stacks, so a
+    /// corrupted chain ends the backtrace early rather than faulting.
+    pub fn from_frame_pointer(mut rbp: u64) -> Self {
+        let mut frames = [0; MAX_FRAMES];
+        let mut len = 0;
+
+        while len < MAX_FRAMES {
+            if rbp == 0 || !rbp.is_multiple_of(8) {
+                break;
+            }
+
+            let Some(stack) = stack::containing(VirtAddr::new_truncate(rbp)) else {
+                break;
+            };
+
+            if rbp + 16 > stack.top.as_u64() {
+                break;
+            }
+
+            let frame = rbp as *const u64;
+            let (next_rbp, return_addr) = unsafe { (*frame, *frame.add(1)) };
+
+            if return_addr == 0 {
+                break;
+            }
+
+            frames[len] = return_addr;
+            len += 1;
+
+            rbp = next_rbp;
+        }
+
+        Backtrace {
+            frames,
+            len,
+            from_exception: false,
+        }
+    }
+
+    /// The return addresses, innermost first
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+/// Keeps the backtrace of an exception for the panic that follows it.
+pub fn set_exception(backtrace: Backtrace) {
+    *EXCEPTION_BACKTRACE.lock() = Some((cpu_id(), backtrace));
+}
+
+/// The backtrace of the exception this CPU is panicking for, if it is
+pub fn take_exception() -> Option<Backtrace> {
+    // Another CPU might have panicked with the lock held
+    let mut exception = EXCEPTION_BACKTRACE.try_lock()?;
+
+    match *exception {
+        Some((cpu, _)) if cpu == cpu_id() => exception.take().map(|(_, backtrace)| backtrace),
+        _ => None,
+    }
+}
+
+fn cpu_id() -> usize {
+    percpu::try_current().map_or(0, |percpu| percpu.cpu_id)
+}
+
+/// Formats the backtrace with one frame per line. The precision (e.g. `{:.8}`) limits the
+/// number of frames that are printed, which is handy on the 25-line VGA screen.
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = f.precision().unwrap_or(MAX_FRAMES).min(self.len);
+
+        writeln!(f, "Backtrace:")?;
+
+        for (index, &addr) in self.frames[..shown].iter().enumerate() {
+            // Return addresses point just past the call, which might be the start of the next
+            // function, so look up the call instruction itself.
+            let back = u64::from(index > 0 || !self.from_exception);
+
+            match resolve(addr - back) {
+                Some((name, offset)) => writeln!(f, "  #{index:<2} {addr:#018x} {name}+{:#x}", offset + back)?,
+                None => writeln!(f, "  #{index:<2} {addr:#018x} <unknown>")?,
+            }
+        }
+
+        if shown < self.len {
+            writeln!(f, "  ... {} more frame(s)", self.len - shown)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the function containing `addr`, returning its name and the offset of `addr` into it.
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    // Keep the compiler from assuming the contents of the table: it's all zeroes in the first
+    // build, and folding that away would shift the addresses the second build depends on.
+    let table: &[u8] = hint::black_box(&SYMBOL_TABLE);
+
+    let mut best = None;
+    let mut pos = 0;
+
+    while let Some(header) = table.get(pos..pos + 10) {
+        let symbol_addr = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let name_len = u16::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+
+        if symbol_addr == 0 || symbol_addr > addr {
+            break;
+        }
+
+        let name = table.get(pos + 10..pos + 10 + name_len)?;
+
+        best = Some((str::from_utf8(name).unwrap_or("<invalid symbol>"), addr - symbol_addr));
+        pos += 10 + name_len;
+    }
+
+    best
+}
@@ -0,0 +1,185 @@
+//! The boot configuration, read from `boot.cfg` in the initrd.
+//!
+//! The bootloader can't pass a command line, so the file takes its place. Each line is a
+//! `key = value` pair, with `#` starting a comment:
+//!
+//! ```text
+//! heap_size = 8M
+//! log_level = debug
+//! keymap = de
+//! repeat_delay = 250
+//! repeat_rate = 30
+//! shells = 2
+//! mouse = off
+//! ```
+//!
+//! Anything left out keeps its default, and lines that don't make sense are logged and skipped.
+
+use core::fmt;
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use log::LevelFilter;
+
+use crate::allocator;
+use crate::initrd;
+use crate::ps2;
+use crate::vt;
+
+/// Where the configuration lives, relative to `initrd/`
+const CONFIG_FILE: &str = "boot.cfg";
+
+static CONFIG: OnceCell<BootConfig> = OnceCell::uninit();
+
+#[derive(Debug, Clone)]
+pub struct BootConfig {
+    /// In bytes. Accepts a `K`, `M` or `G` suffix.
+    pub heap_size: usize,
+    /// The default log level; `loglevel` can still change it later
+    pub log_level: LevelFilter,
+    /// A built-in layout or the name of a file in `/keymaps`
+    pub keymap: &'static str,
+    /// How long a key is held before it repeats, in milliseconds
+    pub repeat_delay: Duration,
+    /// Characters per second
+    pub repeat_rate: u32,
+    /// How many virtual terminals get a shell, starting from the first
+    pub shells: usize,
+    /// Whether to spawn the mouse task
+    pub mouse: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    MissingEquals { line: usize },
+    UnknownKey { line: usize, key: &'static str },
+    InvalidValue { line: usize, key: &'static str, value: &'static str },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingEquals { line } => write!(f, "line {line}: expected `key = value`"),
+            ConfigError::UnknownKey { line, key } => write!(f, "line {line}: unknown key `{key}`"),
+            ConfigError::InvalidValue { line, key, value } => {
+                write!(f, "line {line}: `{value}` isn't a valid {key}")
+            }
+        }
+    }
+}
+
+impl BootConfig {
+    pub const DEFAULT: BootConfig = BootConfig {
+        heap_size: allocator::DEFAULT_HEAP_SIZE,
+        log_level: LevelFilter::Info,
+        keymap: "us",
+        repeat_delay: ps2::DEFAULT_REPEAT_DELAY,
+        repeat_rate: ps2::DEFAULT_REPEAT_RATE,
+        shells: vt::VT_COUNT,
+        mouse: true,
+    };
+
+    /// Parses a configuration file on top of the defaults. Doesn't allocate, so it can run
+    /// before the heap is set up; errors are handed to `on_error` and the line is skipped.
+    pub fn parse(text: &'static str, mut on_error: impl FnMut(ConfigError)) -> BootConfig {
+        let mut config = BootConfig::DEFAULT;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                on_error(ConfigError::MissingEquals { line: line_number });
+                continue;
+            };
+
+            if let Err(err) = config.set(key.trim(), value.trim(), line_number) {
+                on_error(err);
+            }
+        }
+
+        config
+    }
+
+    fn set(&mut self, key: &'static str, value: &'static str, line: usize) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::InvalidValue { line, key, value };
+
+        match key {
+            "heap_size" => {
+                self.heap_size = parse_size(value)
+                    .filter(|&size| size >= allocator::MIN_HEAP_SIZE)
+                    .ok_or_else(invalid)?;
+            }
+            "log_level" => self.log_level = value.parse().map_err(|_| invalid())?,
+            "keymap" => self.keymap = value,
+            "repeat_delay" => {
+                let millis = value.parse().map_err(|_| invalid())?;
+                self.repeat_delay = Duration::from_millis(millis);
+            }
+            "repeat_rate" => self.repeat_rate = value.parse().map_err(|_| invalid())?,
+            "shells" => {
+                self.shells = value
+                    .parse()
+                    .ok()
+                    .filter(|&shells| shells <= vt::VT_COUNT)
+                    .ok_or_else(invalid)?;
+            }
+            "mouse" => self.mouse = parse_bool(value).ok_or_else(invalid)?,
+            _ => return Err(ConfigError::UnknownKey { line, key }),
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads `boot.cfg` from the initrd, if there is one. Doesn't need the heap, so that the heap's
+/// size can come from it; problems are logged.
+pub fn init() {
+    CONFIG.init_once(|| {
+        let Some(file) = initrd::find(CONFIG_FILE) else {
+            return BootConfig::DEFAULT;
+        };
+
+        let Ok(text) = core::str::from_utf8(file) else {
+            log::warn!("{CONFIG_FILE} isn't valid UTF-8; using the defaults");
+            return BootConfig::DEFAULT;
+        };
+
+        BootConfig::parse(text, |err| log::warn!("{CONFIG_FILE}: {err}"))
+    });
+}
+
+/// The boot configuration, or the defaults before `init`
+pub fn get() -> &'static BootConfig {
+    CONFIG.get().unwrap_or(&BootConfig::DEFAULT)
+}
+
+/// A number of bytes, optionally followed by `K`, `M` or `G`
+fn parse_size(value: &str) -> Option<usize> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+
+    let unit = match unit.trim() {
+        "" => 1,
+        "K" | "k" => 1024,
+        "M" | "m" => 1024 * 1024,
+        "G" | "g" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "on" | "true" | "1" => Some(true),
+        "no" | "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
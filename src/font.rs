@@ -0,0 +1,380 @@
+//! Text mode fonts, and the mapping from Unicode characters to the glyphs that show them.
+//!
+//! The VGA card keeps the font for text mode in plane 2 of its memory: 256 glyphs, each 8 dots
+//! wide and stored in a 32-byte slot, one byte per scanline. Out of the box it holds code page
+//! 437, so that's the mapping used until a font with its own Unicode table is loaded. Fonts
+//! come from PSF (version 1 or 2) files in the filesystem. With the `framebuffer` feature, the
+//! framebuffer console draws the same glyphs instead, once it's running.
+
+use core::fmt;
+use core::ptr;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+use crate::fs::{Path, FILESYSTEM};
+use crate::memory;
+use crate::vt;
+
+/// Shown for characters that no glyph maps to
+pub const REPLACEMENT_GLYPH: u8 = 0xfe;
+
+const GLYPH_COUNT: usize = 256;
+/// The size of each glyph's slot in plane 2
+const GLYPH_SLOT: usize = 32;
+/// The tallest font that fits the 80x25 mode's character cells
+pub const MAX_HEIGHT: usize = 16;
+
+/// The Unicode characters that code page 437 shows, from 0x80 to 0xff
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+/// The symbols that code page 437 shows in place of the control characters, from 0x01 to 0x1f
+const CP437_LOW: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+/// The Unicode mapping of the loaded font, or `None` for code page 437
+static UNICODE_MAP: RwLock<Option<BTreeMap<char, u8>>> = RwLock::new(None);
+/// The font that was in plane 2 before the first upload, so that it can be put back
+static ORIGINAL_FONT: OnceCell<Box<[[u8; MAX_HEIGHT]; GLYPH_COUNT]>> = OnceCell::uninit();
+
+#[derive(Debug)]
+pub enum FontError {
+    NotFound,
+    NotPsf,
+    /// Only fonts 8 dots wide and up to 16 scanlines tall fit text mode's character cells
+    UnsupportedSize { width: usize, height: usize },
+    Truncated,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::NotFound => write!(f, "no such file"),
+            FontError::NotPsf => write!(f, "not a PSF font"),
+            FontError::UnsupportedSize { width, height } => {
+                write!(f, "{width}x{height} glyphs don't fit; fonts have to be 8 wide and at most {MAX_HEIGHT} tall")
+            }
+            FontError::Truncated => write!(f, "the font file is truncated"),
+        }
+    }
+}
+
+pub struct Font {
+    /// The first 256 glyphs, one byte per scanline
+    glyphs: Vec<[u8; MAX_HEIGHT]>,
+    unicode: Option<BTreeMap<char, u8>>,
+}
+
+impl Font {
+    /// Parses a PSF1 or PSF2 font.
+    pub fn parse(bytes: &[u8]) -> Result<Font, FontError> {
+        match bytes {
+            [0x36, 0x04, ..] => Font::parse_psf1(bytes),
+            [0x72, 0xb5, 0x4a, 0x86, ..] => Font::parse_psf2(bytes),
+            _ => Err(FontError::NotPsf),
+        }
+    }
+
+    fn parse_psf1(bytes: &[u8]) -> Result<Font, FontError> {
+        const MODE_512: u8 = 0x01;
+        const MODE_HAS_TABLE: u8 = 0x02 | 0x04;
+
+        let (mode, height) = match bytes.get(2..4) {
+            Some(&[mode, height]) => (mode, height as usize),
+            _ => return Err(FontError::Truncated),
+        };
+        let count = if mode & MODE_512 != 0 { 512 } else { 256 };
+
+        let font_data = &bytes[4..];
+        let glyph_bytes = font_data.get(..count * height).ok_or(FontError::Truncated)?;
+
+        let unicode = (mode & MODE_HAS_TABLE != 0).then(|| {
+            let mut map = BTreeMap::new();
+            let table = font_data[count * height..].chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+
+            let mut glyph = 0;
+            let mut in_sequence = false;
+
+            for entry in table {
+                match entry {
+                    0xffff => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    // Sequences of several code points share a glyph; they're skipped
+                    0xfffe => in_sequence = true,
+                    code_point if !in_sequence => add_mapping(&mut map, char::from_u32(code_point as u32), glyph),
+                    _ => {}
+                }
+            }
+
+            map
+        });
+
+        Font::new(8, height, glyph_bytes, height, unicode)
+    }
+
+    fn parse_psf2(bytes: &[u8]) -> Result<Font, FontError> {
+        const FLAG_HAS_TABLE: u32 = 0x01;
+
+        let field = |index: usize| -> Result<usize, FontError> {
+            let start = 4 + index * 4;
+            let field = bytes.get(start..start + 4).ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]) as usize)
+        };
+
+        let header_size = field(1)?;
+        let flags = field(2)? as u32;
+        let count = field(3)?;
+        let glyph_size = field(4)?;
+        let height = field(5)?;
+        let width = field(6)?;
+
+        // Each scanline is padded to a whole byte
+        if glyph_size != height.saturating_mul(width.div_ceil(8)) {
+            return Err(FontError::UnsupportedSize { width, height });
+        }
+
+        let glyphs_end = count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::Truncated)?;
+        let glyph_bytes = bytes.get(header_size..glyphs_end).ok_or(FontError::Truncated)?;
+
+        let unicode = (flags & FLAG_HAS_TABLE != 0).then(|| {
+            let mut map = BTreeMap::new();
+
+            // One entry per glyph, ended by 0xff. Each entry is UTF-8 text, where every
+            // character maps to the glyph, then optionally 0xfe and sequences, which are skipped.
+            for (glyph, entry) in bytes[glyphs_end..].split(|&byte| byte == 0xff).take(count).enumerate() {
+                let singles = entry.split(|&byte| byte == 0xfe).next().unwrap_or(&[]);
+
+                for character in core::str::from_utf8(singles).unwrap_or("").chars() {
+                    add_mapping(&mut map, Some(character), glyph);
+                }
+            }
+
+            map
+        });
+
+        Font::new(width, height, glyph_bytes, glyph_size, unicode)
+    }
+
+    fn new(
+        width: usize,
+        height: usize,
+        glyph_bytes: &[u8],
+        glyph_size: usize,
+        unicode: Option<BTreeMap<char, u8>>,
+    ) -> Result<Font, FontError> {
+        if width != 8 || height == 0 || height > MAX_HEIGHT || glyph_size < height {
+            return Err(FontError::UnsupportedSize { width, height });
+        }
+
+        let glyphs = glyph_bytes
+            .chunks_exact(glyph_size)
+            .take(GLYPH_COUNT)
+            .map(|glyph| {
+                let mut scanlines = [0; MAX_HEIGHT];
+                scanlines[..height].copy_from_slice(&glyph[..height]);
+                scanlines
+            })
+            .collect();
+
+        Ok(Font { glyphs, unicode })
+    }
+
+    /// Puts the font in the VGA card and switches to its Unicode mapping. Fonts without a
+    /// mapping are assumed to be laid out like code page 437.
+    pub fn install(self) {
+        upload(0, &self.glyphs);
+
+        *UNICODE_MAP.write() = self.unicode;
+    }
+}
+
+/// Maps a character to a glyph, unless an earlier glyph already has it or it doesn't fit in
+/// text mode's 256 glyphs.
+fn add_mapping(map: &mut BTreeMap<char, u8>, character: Option<char>, glyph: usize) {
+    if let (Some(character), Ok(glyph)) = (character, u8::try_from(glyph)) {
+        map.entry(character).or_insert(glyph);
+    }
+}
+
+/// Loads a PSF font from the filesystem and installs it.
+pub fn load(path: &Path) -> Result<(), FontError> {
+    let font = {
+        let filesystem = FILESYSTEM.lock();
+        let file = filesystem.get(path).ok_or(FontError::NotFound)?;
+        Font::parse(file.contents())?
+    };
+
+    font.install();
+    Ok(())
+}
+
+/// Puts back the font that the VGA card started with, and the code page 437 mapping.
+pub fn reset() {
+    let Ok(original) = ORIGINAL_FONT.try_get() else {
+        return;
+    };
+
+    upload(0, &original[..]);
+
+    *UNICODE_MAP.write() = None;
+}
+
+/// The glyphs that the VGA card started with.
+pub fn original_glyphs() -> Vec<[u8; MAX_HEIGHT]> {
+    if ORIGINAL_FONT.try_get().is_err() {
+        with_plane_2(|plane| unsafe { save_original(plane) });
+    }
+
+    ORIGINAL_FONT.try_get().map_or_else(|_| Vec::new(), |font| font.to_vec())
+}
+
+/// Replaces a single glyph, and maps `character` to it.
+pub fn define_glyph(character: char, glyph: u8, scanlines: &[u8; MAX_HEIGHT]) {
+    upload(glyph as usize, &[*scanlines]);
+
+    UNICODE_MAP
+        .write()
+        .get_or_insert_with(cp437_map)
+        .insert(character, glyph);
+}
+
+/// The glyph that shows `character`, or `REPLACEMENT_GLYPH` if there isn't one.
+pub fn glyph(character: char) -> u8 {
+    let map = UNICODE_MAP.read();
+
+    match &*map {
+        Some(map) => map.get(&character).copied(),
+        None if matches!(character, ' '..='~') => Some(character as u8),
+        None => cp437_glyph(character),
+    }
+    .unwrap_or(REPLACEMENT_GLYPH)
+}
+
+fn cp437_glyph(character: char) -> Option<u8> {
+    if character == '⌂' {
+        return Some(0x7f);
+    }
+
+    let low = CP437_LOW.chars().position(|low| low == character).map(|index| index + 0x01);
+    let high = || CP437_HIGH.chars().position(|high| high == character).map(|index| index + 0x80);
+
+    low.or_else(high).map(|index| index as u8)
+}
+
+fn cp437_map() -> BTreeMap<char, u8> {
+    let printable = (b' '..=b'~').map(|byte| (byte as char, byte));
+    let low = CP437_LOW.chars().zip(0x01..);
+    let high = CP437_HIGH.chars().zip(0x80..);
+
+    printable.chain(low).chain(high).chain([('⌂', 0x7f)]).collect()
+}
+
+/// Replaces glyphs, starting with `first`, wherever text is drawn from: the framebuffer
+/// console's font if it's in use, or else plane 2.
+fn upload(first: usize, glyphs: &[[u8; MAX_HEIGHT]]) {
+    #[cfg(feature = "framebuffer")]
+    if crate::framebuffer::set_glyphs(first, glyphs) {
+        return;
+    }
+
+    with_plane_2(|plane| unsafe {
+        save_original(plane);
+
+        for (index, glyph) in glyphs.iter().enumerate().take(GLYPH_COUNT - first.min(GLYPH_COUNT)) {
+            let slot = plane.add((first + index) * GLYPH_SLOT);
+
+            for (scanline, &bits) in glyph.iter().enumerate() {
+                ptr::write_volatile(slot.add(scanline), bits);
+            }
+        }
+    });
+}
+
+/// Copies the font that's in plane 2 the first time it's about to be overwritten.
+unsafe fn save_original(plane: *mut u8) {
+    ORIGINAL_FONT.init_once(|| {
+        let mut font = Box::new([[0; MAX_HEIGHT]; GLYPH_COUNT]);
+
+        for (index, glyph) in font.iter_mut().enumerate() {
+            for (offset, bits) in glyph.iter_mut().enumerate() {
+                *bits = ptr::read_volatile(plane.add(index * GLYPH_SLOT + offset));
+            }
+        }
+
+        font
+    });
+}
+
+const SEQUENCER_INDEX: u16 = 0x3c4;
+const GRAPHICS_INDEX: u16 = 0x3ce;
+
+const SEQUENCER_RESET: u8 = 0x00;
+const SEQUENCER_MAP_MASK: u8 = 0x02;
+const SEQUENCER_MEMORY_MODE: u8 = 0x04;
+const GRAPHICS_READ_MAP: u8 = 0x04;
+const GRAPHICS_MODE: u8 = 0x05;
+const GRAPHICS_MISC: u8 = 0x06;
+
+/// Reads a register through an index/data port pair. The data port is the one after the index.
+fn read_register(index_port: u16, register: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(index_port).write(register);
+        Port::<u8>::new(index_port + 1).read()
+    }
+}
+
+fn write_register(index_port: u16, register: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(index_port).write(register);
+        Port::<u8>::new(index_port + 1).write(value);
+    }
+}
+
+/// Maps plane 2 at 0xa0000 on its own, runs `f` with a pointer to it, and then puts text mode
+/// back. Text memory isn't reachable in between, so the terminal on screen is locked the whole
+/// time.
+fn with_plane_2<R>(f: impl FnOnce(*mut u8) -> R) -> R {
+    let _terminal = vt::lock(vt::active());
+
+    without_interrupts(|| {
+        let map_mask = read_register(SEQUENCER_INDEX, SEQUENCER_MAP_MASK);
+        let memory_mode = read_register(SEQUENCER_INDEX, SEQUENCER_MEMORY_MODE);
+        let read_map = read_register(GRAPHICS_INDEX, GRAPHICS_READ_MAP);
+        let mode = read_register(GRAPHICS_INDEX, GRAPHICS_MODE);
+        let misc = read_register(GRAPHICS_INDEX, GRAPHICS_MISC);
+
+        // Write to plane 2 only, with odd/even addressing off
+        write_register(SEQUENCER_INDEX, SEQUENCER_RESET, 0x01);
+        write_register(SEQUENCER_INDEX, SEQUENCER_MAP_MASK, 0x04);
+        write_register(SEQUENCER_INDEX, SEQUENCER_MEMORY_MODE, 0x07);
+        write_register(SEQUENCER_INDEX, SEQUENCER_RESET, 0x03);
+
+        // Read from plane 2, and map it at 0xa0000
+        write_register(GRAPHICS_INDEX, GRAPHICS_READ_MAP, 0x02);
+        write_register(GRAPHICS_INDEX, GRAPHICS_MODE, 0x00);
+        write_register(GRAPHICS_INDEX, GRAPHICS_MISC, 0x04);
+
+        let plane = memory::phys_to_virt(PhysAddr::new(0xa0000)).as_mut_ptr();
+        let result = f(plane);
+
+        write_register(SEQUENCER_INDEX, SEQUENCER_RESET, 0x01);
+        write_register(SEQUENCER_INDEX, SEQUENCER_MAP_MASK, map_mask);
+        write_register(SEQUENCER_INDEX, SEQUENCER_MEMORY_MODE, memory_mode);
+        write_register(SEQUENCER_INDEX, SEQUENCER_RESET, 0x03);
+
+        write_register(GRAPHICS_INDEX, GRAPHICS_READ_MAP, read_map);
+        write_register(GRAPHICS_INDEX, GRAPHICS_MODE, mode);
+        write_register(GRAPHICS_INDEX, GRAPHICS_MISC, misc);
+
+        result
+    })
+}
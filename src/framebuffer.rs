@@ -0,0 +1,363 @@
+//! A linear framebuffer console, for more text than 80x25.
+//!
+//! QEMU's standard VGA card (like Bochs') has the Bochs Graphics Adaptor interface, which sets
+//! a graphics mode through a pair of I/O ports and puts the pixels behind the card's first PCI
+//! BAR. The console keeps a grid of `ScreenChar`s just like the VGA text buffer, and draws each
+//! one with the loaded font, so `Writer` and the virtual terminals work on it unchanged. The 16
+//! text mode colours are drawn in 24-bit colour through `PALETTE`.
+//!
+//! Only built with the `framebuffer` feature.
+
+use core::array;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use volatile::Volatile;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+
+use crate::font;
+use crate::graphics::{Draw, Rgb};
+use crate::memory;
+use crate::pci;
+use crate::vga_buffer::{BufWrite, ColourCode, CursorShape, ScreenChar};
+use crate::vt;
+
+/// The resolution that's asked for, in pixels
+pub const WIDTH: usize = 1024;
+pub const HEIGHT: usize = 768;
+
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = font::MAX_HEIGHT;
+
+/// The size of the console, in characters
+pub const COLUMNS: usize = WIDTH / GLYPH_WIDTH;
+pub const ROWS: usize = HEIGHT / GLYPH_HEIGHT;
+
+const BGA_VENDOR_ID: u16 = 0x1234;
+const BGA_DEVICE_ID: u16 = 0x1111;
+
+const BGA_INDEX: u16 = 0x01ce;
+const BGA_DATA: u16 = 0x01cf;
+
+const BGA_ID: u16 = 0x00;
+const BGA_X_RESOLUTION: u16 = 0x01;
+const BGA_Y_RESOLUTION: u16 = 0x02;
+const BGA_BITS_PER_PIXEL: u16 = 0x03;
+const BGA_ENABLE: u16 = 0x04;
+const BGA_VIRTUAL_WIDTH: u16 = 0x06;
+
+/// The first version with 32 bits per pixel
+const BGA_MIN_VERSION: u16 = 0xb0c2;
+
+const BGA_ENABLED: u16 = 0x01;
+const BGA_LINEAR_FRAMEBUFFER: u16 = 0x40;
+
+/// The colours that the text mode colours are drawn in, in the order of `Colour`
+pub const PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    NoDevice,
+    UnsupportedVersion(u16),
+    NoFramebuffer,
+    Map(MapToError<Size4KiB>),
+}
+
+/// The pixels on screen, 32 bits each.
+pub struct Framebuffer {
+    base: *mut u32,
+    width: usize,
+    height: usize,
+    /// The number of pixels from the start of one line to the next
+    stride: usize,
+}
+
+// SAFETY: the framebuffer is only reached through the console, which the active terminal's lock
+// guards
+unsafe impl Send for Framebuffer {}
+
+impl Draw for Framebuffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Reading the card's memory is slow, so draw into a `DoubleBuffer` for anything that
+    /// blends a lot.
+    fn pixel(&self, x: usize, y: usize) -> Rgb {
+        Rgb::from_pixel(unsafe { ptr::read_volatile(self.base.add(y * self.stride + x)) })
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, colour: Rgb) {
+        unsafe { ptr::write_volatile(self.base.add(y * self.stride + x), colour.pixel()) };
+    }
+
+    fn begin_frame(&mut self) {
+        wait_for_retrace();
+    }
+}
+
+/// Waits for the start of the next vertical retrace, so that the screen isn't changed while
+/// it's being scanned out. Gives up after a while if nothing's there to report one.
+fn wait_for_retrace() {
+    const INPUT_STATUS: u16 = 0x3da;
+    const RETRACE: u8 = 0x08;
+    const TRIES: usize = 100_000;
+
+    let mut status = Port::<u8>::new(INPUT_STATUS);
+
+    // Let any retrace that's already going finish, then wait for the next one
+    for in_retrace in [false, true] {
+        for _ in 0..TRIES {
+            if (unsafe { status.read() } & RETRACE != 0) == in_retrace {
+                break;
+            }
+        }
+    }
+}
+
+/// A text console drawn onto the framebuffer.
+pub struct FramebufferConsole {
+    framebuffer: Framebuffer,
+    cells: Vec<[Volatile<ScreenChar>; COLUMNS]>,
+    glyphs: Vec<[u8; GLYPH_HEIGHT]>,
+    cursor: (usize, usize),
+    cursor_shape: Option<CursorShape>,
+}
+
+impl FramebufferConsole {
+    fn new(framebuffer: Framebuffer, glyphs: Vec<[u8; GLYPH_HEIGHT]>) -> FramebufferConsole {
+        let mut console = FramebufferConsole {
+            framebuffer,
+            cells: (0..ROWS).map(|_| array::from_fn(|_| Volatile::new(ScreenChar::default()))).collect(),
+            glyphs,
+            cursor: (0, 0),
+            cursor_shape: Some(CursorShape::default()),
+        };
+
+        console.redraw();
+        console
+    }
+
+    /// Draws every character again.
+    pub fn redraw(&mut self) {
+        for row in 0..ROWS {
+            for col in 0..COLUMNS {
+                self.draw_cell(row, col);
+            }
+        }
+    }
+
+    /// Draws one character, with the cursor over it if it's there.
+    fn draw_cell(&mut self, row: usize, col: usize) {
+        let character = self.cells[row][col].read();
+        let ColourCode(colour) = character.colour_code();
+
+        let foreground = PALETTE[(colour & 0x0f) as usize];
+        let background = PALETTE[(colour >> 4) as usize];
+
+        let glyph = self.glyphs.get(character.ascii_character() as usize).copied().unwrap_or_default();
+        let cursor = self.cursor_shape.filter(|_| self.cursor == (row, col));
+
+        for (scanline, &bits) in glyph.iter().enumerate() {
+            let bits = match cursor {
+                Some(shape) if (shape.start as usize..=shape.end as usize).contains(&scanline) => !bits,
+                _ => bits,
+            };
+
+            for x in 0..GLYPH_WIDTH {
+                let colour = if bits & (0x80 >> x) != 0 { foreground } else { background };
+
+                self.framebuffer.write_pixel(col * GLYPH_WIDTH + x, row * GLYPH_HEIGHT + scanline, colour);
+            }
+        }
+    }
+}
+
+impl BufWrite for FramebufferConsole {
+    fn write_char(&mut self, colour_code: ColourCode, ascii_code: u8, row: usize, col: usize) -> fmt::Result {
+        if ascii_code == b'\n' {
+            return Err(fmt::Error);
+        }
+
+        let cell = self.cells.get_mut(row).and_then(|row| row.get_mut(col)).ok_or(fmt::Error)?;
+        let character = ScreenChar::new(ascii_code, colour_code);
+
+        // Whole terminals are written out when they're switched to or scrolled, so leave
+        // the pixels of characters that stay the same alone
+        if cell.read() != character {
+            cell.write(character);
+            self.draw_cell(row, col);
+        }
+
+        Ok(())
+    }
+
+    fn clear_screen(&mut self, colour: ColourCode) {
+        for row in 0..ROWS {
+            for col in 0..COLUMNS {
+                self.write_char(colour, b' ', row, col).ok();
+            }
+        }
+    }
+
+    fn read_char(&mut self, row: usize, col: usize) -> Option<ScreenChar> {
+        Some(self.cells.get(row)?.get(col)?.read())
+    }
+
+    fn move_cursor(&mut self, row: usize, col: usize) {
+        let (old_row, old_col) = core::mem::replace(&mut self.cursor, (row.min(ROWS - 1), col.min(COLUMNS - 1)));
+
+        self.draw_cell(old_row, old_col);
+        self.draw_cell(self.cursor.0, self.cursor.1);
+    }
+
+    fn cursor_shape(&self) -> Option<CursorShape> {
+        self.cursor_shape
+    }
+
+    fn set_cursor_shape(&mut self, shape: Option<CursorShape>) {
+        self.cursor_shape = shape;
+        self.draw_cell(self.cursor.0, self.cursor.1);
+    }
+}
+
+struct ConsoleCell(UnsafeCell<FramebufferConsole>);
+
+// SAFETY: see `console`
+unsafe impl Sync for ConsoleCell {}
+
+static CONSOLE: OnceCell<ConsoleCell> = OnceCell::uninit();
+
+/// The console, once `init` has switched to it.
+///
+/// # Safety
+/// Like `vga_buffer::hardware_buffer`, only the active terminal may draw on it, and only with
+/// its lock held.
+pub unsafe fn console() -> Option<&'static mut FramebufferConsole> {
+    CONSOLE.get().map(|cell| &mut *cell.0.get())
+}
+
+/// Whether `init` has switched to the console
+pub fn is_active() -> bool {
+    CONSOLE.is_initialized()
+}
+
+/// Runs `f` with the framebuffer, for drawing over the console. The terminal on screen is
+/// locked in the meantime, and `redraw` puts it back afterwards. Returns `None` if the console
+/// isn't in use.
+pub fn with_framebuffer<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Option<R> {
+    let _terminal = vt::lock(vt::active());
+
+    // SAFETY: the active terminal's lock is held
+    unsafe { console() }.map(|console| f(&mut console.framebuffer))
+}
+
+/// Draws the whole console again, over anything that's been drawn on the framebuffer.
+pub fn redraw() {
+    let _terminal = vt::lock(vt::active());
+
+    // SAFETY: the active terminal's lock is held
+    if let Some(console) = unsafe { console() } {
+        console.redraw();
+    }
+}
+
+/// Replaces glyphs in the console's font, starting with `first`, and draws everything again.
+/// Returns `false` if the console isn't in use.
+pub fn set_glyphs(first: usize, glyphs: &[[u8; GLYPH_HEIGHT]]) -> bool {
+    let _terminal = vt::lock(vt::active());
+
+    // SAFETY: the active terminal's lock is held
+    let Some(console) = (unsafe { console() }) else {
+        return false;
+    };
+
+    for (slot, glyph) in console.glyphs.iter_mut().skip(first).zip(glyphs) {
+        *slot = *glyph;
+    }
+
+    console.redraw();
+    true
+}
+
+fn bga_read(register: u16) -> u16 {
+    unsafe {
+        Port::<u16>::new(BGA_INDEX).write(register);
+        Port::<u16>::new(BGA_DATA).read()
+    }
+}
+
+fn bga_write(register: u16, value: u16) {
+    unsafe {
+        Port::<u16>::new(BGA_INDEX).write(register);
+        Port::<u16>::new(BGA_DATA).write(value);
+    }
+}
+
+/// Switches the Bochs Graphics Adaptor to `WIDTH`x`HEIGHT` and moves the terminals over to the
+/// framebuffer console. On error, the screen is left in text mode, and the terminals only use
+/// its 80x25 (see `vga_buffer::screen_size`).
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), FramebufferError> {
+    let device = pci::find(BGA_VENDOR_ID, BGA_DEVICE_ID).ok_or(FramebufferError::NoDevice)?;
+
+    let version = bga_read(BGA_ID);
+    if version < BGA_MIN_VERSION {
+        return Err(FramebufferError::UnsupportedVersion(version));
+    }
+
+    let address = device.memory_bar(0).ok_or(FramebufferError::NoFramebuffer)?;
+
+    // The font lives in the card's memory, which graphics modes draw over
+    let glyphs = font::original_glyphs();
+
+    // The framebuffer can be wider than the screen, but not by much
+    let size = (WIDTH * HEIGHT * 4 * 2) as u64;
+    let base = memory::map_mmio(address, size, mapper, frame_allocator).map_err(FramebufferError::Map)?;
+
+    bga_write(BGA_ENABLE, 0);
+    bga_write(BGA_X_RESOLUTION, WIDTH as u16);
+    bga_write(BGA_Y_RESOLUTION, HEIGHT as u16);
+    bga_write(BGA_BITS_PER_PIXEL, 32);
+    bga_write(BGA_ENABLE, BGA_ENABLED | BGA_LINEAR_FRAMEBUFFER);
+
+    let framebuffer = Framebuffer {
+        base: base.as_mut_ptr(),
+        width: WIDTH,
+        height: HEIGHT,
+        stride: (bga_read(BGA_VIRTUAL_WIDTH) as usize).clamp(WIDTH, WIDTH * 2),
+    };
+
+    CONSOLE.init_once(|| ConsoleCell(UnsafeCell::new(FramebufferConsole::new(framebuffer, glyphs))));
+    vt::redraw();
+
+    Ok(())
+}
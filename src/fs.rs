@@ -1,6 +1,12 @@
-use core::{ops::{Index, Range, IndexMut}, borrow::Borrow};
+use core::{ops::{Index, Range, IndexMut}, borrow::Borrow, fmt};
 
 use alloc::{collections::{BTreeMap}, string::{String, FromUtf8Error}, vec::{Vec}, slice};
+use spin::Mutex;
+
+/// The kernel's filesystem. Starts out with the contents of the initrd.
+pub static FILESYSTEM: Mutex<MemoryFS> = Mutex::new(MemoryFS {
+    items: BTreeMap::new()
+});
 
 #[derive(Clone, Copy, Hash, PartialEq, PartialOrd, Ord, Eq, Debug)]
 pub enum FsError {
@@ -24,6 +30,42 @@ pub struct Directory {
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Path(Vec<Directory>);
 
+impl Path {
+    /// Parses a `/`-separated path to a file. Every component but the last is a folder, and
+    /// empty components are skipped, so `/keymaps//de.keymap` and `keymaps/de.keymap` are the
+    /// same path.
+    pub fn new(path: &str) -> Path {
+        let mut components: Vec<_> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| Directory {
+                variant: DirectoryType::Folder,
+                name: component.into()
+            })
+            .collect();
+
+        if let Some(file) = components.last_mut() {
+            file.variant = DirectoryType::File;
+        }
+
+        Path(components)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "/");
+        }
+
+        for directory in &self.0 {
+            write!(f, "/{}", directory.name)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Permissions {
     read: bool,
@@ -38,10 +80,36 @@ pub struct File {
 }
 
 impl File {
+    /// Creates a readable, writable file.
+    pub fn new(contents: Vec<u8>) -> File {
+        File {
+            permissions: Permissions {
+                read: true,
+                write: true,
+                execute: false
+            },
+            contents
+        }
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+
     pub fn overwrite(&mut self, new_content: Vec<u8>) {
         self.contents = new_content
     }
 
+    /// Adds to the end of the file.
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.contents.extend_from_slice(bytes)
+    }
+
+    /// Removes the first `len` bytes, or everything if the file is shorter.
+    pub fn remove_front(&mut self, len: usize) {
+        self.contents.drain(..len.min(self.contents.len()));
+    }
+
     pub fn read_string(&self) -> Result<String, FromUtf8Error> {
         String::from_utf8(self.contents.clone())
     }
@@ -101,6 +169,29 @@ pub struct MemoryFS {
     items: BTreeMap<Path, File>
 }
 
+impl MemoryFS {
+    /// Creates a file, replacing any file that already exists at `path`.
+    pub fn create(&mut self, path: Path, contents: Vec<u8>) {
+        self.items.insert(path, File::new(contents));
+    }
+
+    /// Like indexing, but returns `None` instead of panicking if the file doesn't exist.
+    pub fn get(&self, path: &Path) -> Option<&File> {
+        self.items.get(path)
+    }
+
+    /// Adds to the end of a file in place, creating it first if it doesn't exist.
+    pub fn append(&mut self, path: Path, bytes: &[u8]) -> &mut File {
+        let file = self.items.entry(path).or_insert_with(|| File::new(Vec::new()));
+        file.append(bytes);
+        file
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.items.keys()
+    }
+}
+
 impl Index<Path> for MemoryFS {
     type Output = File;
 
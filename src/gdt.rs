@@ -1,50 +1,90 @@
-use lazy_static::lazy_static;
+use alloc::boxed::Box;
+use alloc::format;
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtAddr;
+
+use crate::stack;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+/// The size of each interrupt stack, in pages
+const IST_STACK_PAGES: u64 = 5;
+
+/// The GDT and TSS of a single CPU. Every CPU needs its own TSS, since the TSS holds the
+/// interrupt stacks and a CPU marks its TSS as busy while it's loaded.
+pub struct CpuTables {
+    gdt: GlobalDescriptorTable,
+    selectors: Selectors,
+}
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+}
+
+impl CpuTables {
+    /// Builds the GDT and TSS for the CPU `cpu_id`. The interrupt stacks are mapped with guard
+    /// pages, so this has to be called after paging and the heap are set up.
+    pub fn new(
+        cpu_id: usize,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<&'static CpuTables, MapToError<Size4KiB>> {
+        let double_fault_stack = stack::alloc_stack(
+            format!("CPU {cpu_id} double fault stack").leak(),
+            IST_STACK_PAGES,
+            mapper,
+            frame_allocator,
+        )?;
+        // The page fault handler gets its own stack so that it can still run (and report the
+        // overflow) when the faulting stack is the one that overflowed.
+        let page_fault_stack = stack::alloc_stack(
+            format!("CPU {cpu_id} page fault stack").leak(),
+            IST_STACK_PAGES,
+            mapper,
+            frame_allocator,
+        )?;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack.top;
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = page_fault_stack.top;
+        let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+
+        Ok(Box::leak(Box::new(CpuTables {
             gdt,
-            Selectors {
+            selectors: Selectors {
                 code_selector,
                 tss_selector,
             },
-        )
-    };
-}
+        })))
+    }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+    /// Loads the tables on the current CPU.
+    pub fn load(&'static self) {
+        use x86_64::instructions::segmentation::{Segment, CS};
+        use x86_64::instructions::tables::load_tss;
+
+        self.gdt.load();
+        unsafe {
+            CS::set_reg(self.selectors.code_selector);
+            load_tss(self.selectors.tss_selector);
+        }
+    }
 }
 
-pub fn init_gdt() {
-    use x86_64::instructions::segmentation::{Segment, CS};
-    use x86_64::instructions::tables::load_tss;
+/// Builds and loads the GDT and TSS of the bootstrap processor.
+pub fn init_gdt(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    CpuTables::new(0, mapper, frame_allocator)?.load();
 
-    GDT.0.load();
-    unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
-    }
+    Ok(())
 }
@@ -0,0 +1,222 @@
+//! Uncompressed Windows bitmaps, in 24 or 32 bits per pixel.
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use super::Rgb;
+use crate::fs::{Path, FILESYSTEM};
+
+/// An image with an alpha channel.
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// Row by row from the top, as 0xAARRGGBB
+    pixels: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum BmpError {
+    NotFound,
+    NotBmp,
+    /// Only uncompressed images with 24 or 32 bits per pixel are supported
+    Unsupported { bits_per_pixel: u16, compression: u32 },
+    Truncated,
+}
+
+impl fmt::Display for BmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BmpError::NotFound => write!(f, "no such file"),
+            BmpError::NotBmp => write!(f, "not a BMP image"),
+            BmpError::Unsupported { bits_per_pixel, compression } => {
+                write!(f, "{bits_per_pixel} bits per pixel with compression {compression} isn't supported")
+            }
+            BmpError::Truncated => write!(f, "the image is truncated"),
+        }
+    }
+}
+
+impl Image {
+    /// An image of a single colour.
+    pub fn filled(width: usize, height: usize, colour: Rgb, alpha: u8) -> Image {
+        Image {
+            width,
+            height,
+            pixels: alloc::vec![(alpha as u32) << 24 | colour.pixel(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The colour and alpha of a pixel. Panics if it's out of bounds.
+    pub fn pixel(&self, x: usize, y: usize) -> (Rgb, u8) {
+        let pixel = self.pixels[y * self.width + x];
+
+        (Rgb::from_pixel(pixel), (pixel >> 24) as u8)
+    }
+
+    /// Loads a BMP image from the filesystem.
+    pub fn load(path: &Path) -> Result<Image, BmpError> {
+        let filesystem = FILESYSTEM.lock();
+        let file = filesystem.get(path).ok_or(BmpError::NotFound)?;
+
+        Image::parse_bmp(file.contents())
+    }
+
+    /// Parses an uncompressed BMP. 32-bit images keep their alpha channel, unless it's empty
+    /// everywhere, which usually means it isn't used.
+    pub fn parse_bmp(bytes: &[u8]) -> Result<Image, BmpError> {
+        const BI_RGB: u32 = 0;
+        const BI_BITFIELDS: u32 = 3;
+
+        if !bytes.starts_with(b"BM") {
+            return Err(BmpError::NotBmp);
+        }
+
+        let u16_at = |offset: usize| -> Result<u16, BmpError> {
+            let field = bytes.get(offset..offset + 2).ok_or(BmpError::Truncated)?;
+            Ok(u16::from_le_bytes([field[0], field[1]]))
+        };
+        let u32_at = |offset: usize| -> Result<u32, BmpError> {
+            let field = bytes.get(offset..offset + 4).ok_or(BmpError::Truncated)?;
+            Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+        };
+
+        let data_offset = u32_at(0x0a)? as usize;
+        let width = u32_at(0x12)? as i32;
+        let height = u32_at(0x16)? as i32;
+        let bits_per_pixel = u16_at(0x1c)?;
+        let compression = u32_at(0x1e)?;
+
+        match (bits_per_pixel, compression) {
+            (24, BI_RGB) | (32, BI_RGB | BI_BITFIELDS) => {}
+            _ => return Err(BmpError::Unsupported { bits_per_pixel, compression }),
+        }
+
+        if width <= 0 || height == 0 {
+            return Err(BmpError::NotBmp);
+        }
+
+        // Images are stored bottom row first, unless the height is negative
+        let top_down = height < 0;
+        let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+        let bytes_per_pixel = bits_per_pixel as usize / 8;
+        // Rows are padded to a multiple of 4 bytes
+        let row_size = (width * bytes_per_pixel).next_multiple_of(4);
+        // The sizes come straight from the header, so they can be anything
+        let data_end = row_size
+            .checked_mul(height)
+            .and_then(|size| size.checked_add(data_offset))
+            .ok_or(BmpError::Truncated)?;
+        let data = bytes.get(data_offset..data_end).ok_or(BmpError::Truncated)?;
+
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let row = if top_down { y } else { height - 1 - y };
+            let row = &data[row * row_size..][..width * bytes_per_pixel];
+
+            pixels.extend(row.chunks_exact(bytes_per_pixel).map(|pixel| {
+                let alpha = pixel.get(3).copied().unwrap_or(0xff);
+                u32::from_le_bytes([pixel[0], pixel[1], pixel[2], alpha])
+            }));
+        }
+
+        if bits_per_pixel == 32 && pixels.iter().all(|pixel| pixel >> 24 == 0) {
+            pixels.iter_mut().for_each(|pixel| *pixel |= 0xff << 24);
+        }
+
+        Ok(Image { width, height, pixels })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BMP with a 54 byte header, followed by `data`
+    fn bmp(width: i32, height: i32, bits_per_pixel: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 54];
+
+        bytes[0..2].copy_from_slice(b"BM");
+        bytes[0x02..0x06].copy_from_slice(&(54 + data.len() as u32).to_le_bytes());
+        bytes[0x0a..0x0e].copy_from_slice(&54u32.to_le_bytes());
+        bytes[0x0e..0x12].copy_from_slice(&40u32.to_le_bytes());
+        bytes[0x12..0x16].copy_from_slice(&width.to_le_bytes());
+        bytes[0x16..0x1a].copy_from_slice(&height.to_le_bytes());
+        bytes[0x1a..0x1c].copy_from_slice(&1u16.to_le_bytes());
+        bytes[0x1c..0x1e].copy_from_slice(&bits_per_pixel.to_le_bytes());
+        bytes.extend_from_slice(data);
+
+        bytes
+    }
+
+    /// Two 24-bit rows of two pixels, padded from 6 bytes to 8: red and green, then blue and
+    /// white. Pixels are stored blue first.
+    const ROWS: [u8; 16] = [
+        0x00, 0x00, 0xff, 0x00, 0xff, 0x00, 0, 0,
+        0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0, 0,
+    ];
+
+    const RED: Rgb = Rgb::new(0xff, 0x00, 0x00);
+    const GREEN: Rgb = Rgb::new(0x00, 0xff, 0x00);
+    const BLUE: Rgb = Rgb::new(0x00, 0x00, 0xff);
+
+    #[test_case]
+    fn parse_bottom_up() {
+        let image = Image::parse_bmp(&bmp(2, 2, 24, &ROWS)).unwrap();
+
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.pixel(0, 0), (BLUE, 0xff));
+        assert_eq!(image.pixel(1, 0), (Rgb::WHITE, 0xff));
+        assert_eq!(image.pixel(0, 1), (RED, 0xff));
+        assert_eq!(image.pixel(1, 1), (GREEN, 0xff));
+    }
+
+    #[test_case]
+    fn parse_top_down() {
+        let image = Image::parse_bmp(&bmp(2, -2, 24, &ROWS)).unwrap();
+
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.pixel(0, 0), (RED, 0xff));
+        assert_eq!(image.pixel(1, 0), (GREEN, 0xff));
+        assert_eq!(image.pixel(0, 1), (BLUE, 0xff));
+        assert_eq!(image.pixel(1, 1), (Rgb::WHITE, 0xff));
+    }
+
+    #[test_case]
+    fn parse_alpha() {
+        let image = Image::parse_bmp(&bmp(2, 1, 32, &[0xff, 0, 0, 0x80, 0, 0, 0xff, 0])).unwrap();
+        assert_eq!(image.pixel(0, 0), (BLUE, 0x80));
+        assert_eq!(image.pixel(1, 0), (RED, 0x00));
+
+        // An alpha channel that's empty everywhere isn't used
+        let image = Image::parse_bmp(&bmp(1, 1, 32, &[0, 0xff, 0, 0])).unwrap();
+        assert_eq!(image.pixel(0, 0), (GREEN, 0xff));
+    }
+
+    #[test_case]
+    fn parse_truncated() {
+        let image = bmp(2, 2, 24, &ROWS);
+
+        // Missing the padding at the end of the last row
+        assert!(matches!(Image::parse_bmp(&image[..image.len() - 2]), Err(BmpError::Truncated)));
+        assert!(matches!(Image::parse_bmp(&image[..0x14]), Err(BmpError::Truncated)));
+    }
+
+    #[test_case]
+    fn parse_overflowing_header() {
+        assert!(matches!(Image::parse_bmp(&bmp(i32::MAX, i32::MAX, 32, &ROWS)), Err(BmpError::Truncated)));
+        assert!(matches!(Image::parse_bmp(&bmp(i32::MAX, i32::MIN, 24, &ROWS)), Err(BmpError::Truncated)));
+        assert!(matches!(Image::parse_bmp(&bmp(-2, 2, 24, &ROWS)), Err(BmpError::NotBmp)));
+    }
+}
@@ -0,0 +1,497 @@
+//! Software 2D drawing: lines, rectangles, circles, alpha blending and images.
+//!
+//! Everything draws through the `Draw` trait, which only needs to read and write single pixels.
+//! `Surface` is a plain buffer in memory, so it works (and can be checked) without a screen;
+//! the framebuffer implements `Draw` too. `DoubleBuffer` draws off screen and copies only the
+//! rectangles that changed on `flush`, so half-drawn frames are never shown.
+
+pub mod bmp;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::tui::Rect;
+
+#[allow(unused_imports)]
+pub use self::bmp::{BmpError, Image};
+
+/// A 24-bit colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0x00, 0x00, 0x00);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb { red, green, blue }
+    }
+
+    /// The colour as a 32-bit pixel, laid out as 0x00RRGGBB
+    pub fn pixel(self) -> u32 {
+        (self.red as u32) << 16 | (self.green as u32) << 8 | self.blue as u32
+    }
+
+    pub fn from_pixel(pixel: u32) -> Rgb {
+        Rgb::new((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+    }
+
+    /// `over` drawn on top of this colour, where an `alpha` of 255 covers it completely.
+    pub fn blend(self, over: Rgb, alpha: u8) -> Rgb {
+        let mix = |under: u8, over: u8| {
+            let alpha = alpha as u32;
+            ((under as u32 * (255 - alpha) + over as u32 * alpha + 127) / 255) as u8
+        };
+
+        Rgb::new(mix(self.red, over.red), mix(self.green, over.green), mix(self.blue, over.blue))
+    }
+}
+
+/// Something made of pixels that can be drawn on. Coordinates off the edge are clipped, so
+/// shapes can hang off the side.
+pub trait Draw {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+
+    /// Reads a pixel back, for blending. Only called for pixels that are on the surface.
+    fn pixel(&self, x: usize, y: usize) -> Rgb;
+
+    /// Sets a pixel that's on the surface, without `touch`ing it.
+    fn write_pixel(&mut self, x: usize, y: usize, colour: Rgb);
+
+    /// Called with the area that each drawing operation is about to cover, already clipped.
+    /// `DoubleBuffer` uses it to find out what to flush.
+    fn touch(&mut self, _area: Rect) {}
+
+    /// Called before a `DoubleBuffer` copies a frame onto the surface. Screens can wait for a
+    /// good moment to change here; surfaces in memory have no reason to.
+    fn begin_frame(&mut self) {}
+
+    /// The whole surface
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, colour: Rgb) {
+        if let Some((x, y)) = clip(self.bounds(), x, y) {
+            self.touch(Rect::new(x, y, 1, 1));
+            self.write_pixel(x, y, colour);
+        }
+    }
+
+    /// Draws `colour` over a pixel, where an `alpha` of 255 is solid.
+    fn blend_pixel(&mut self, x: isize, y: isize, colour: Rgb, alpha: u8) {
+        if let Some((x, y)) = clip(self.bounds(), x, y) {
+            self.touch(Rect::new(x, y, 1, 1));
+            blend(self, x, y, colour, alpha);
+        }
+    }
+
+    fn clear(&mut self, colour: Rgb) {
+        self.fill_rect(self.bounds(), colour);
+    }
+
+    /// A one pixel wide line from one point to the other, both ends included.
+    fn line(&mut self, (x0, y0): (isize, isize), (x1, y1): (isize, isize), colour: Rgb) {
+        self.touch(clipped(self.bounds(), x0.min(x1), y0.min(y1), x0.max(x1) + 1, y0.max(y1) + 1));
+
+        // Bresenham's algorithm
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            plot(self, x, y, colour);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// The outline of a rectangle, one pixel thick.
+    fn rect(&mut self, area: Rect, colour: Rgb) {
+        if area.is_empty() {
+            return;
+        }
+
+        let (left, top) = (area.x, area.y);
+        let (right, bottom) = (area.x + area.width - 1, area.y + area.height - 1);
+
+        self.fill_rect(Rect::new(left, top, area.width, 1), colour);
+        self.fill_rect(Rect::new(left, bottom, area.width, 1), colour);
+        self.fill_rect(Rect::new(left, top, 1, area.height), colour);
+        self.fill_rect(Rect::new(right, top, 1, area.height), colour);
+    }
+
+    fn fill_rect(&mut self, area: Rect, colour: Rgb) {
+        let area = area.intersection(self.bounds());
+        self.touch(area);
+
+        for y in area.y..area.y + area.height {
+            for x in area.x..area.x + area.width {
+                self.write_pixel(x, y, colour);
+            }
+        }
+    }
+
+    /// Draws `colour` over a rectangle, where an `alpha` of 255 is solid.
+    fn blend_rect(&mut self, area: Rect, colour: Rgb, alpha: u8) {
+        let area = area.intersection(self.bounds());
+        self.touch(area);
+
+        for y in area.y..area.y + area.height {
+            for x in area.x..area.x + area.width {
+                blend(self, x, y, colour, alpha);
+            }
+        }
+    }
+
+    /// The outline of a circle, one pixel thick.
+    fn circle(&mut self, (cx, cy): (isize, isize), radius: usize, colour: Rgb) {
+        self.touch(circle_bounds(self.bounds(), cx, cy, radius));
+
+        // The midpoint algorithm, drawing all eight octants at once
+        let (mut x, mut y) = (radius as isize, 0);
+        let mut error = 1 - x;
+
+        while x >= y {
+            for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                plot(self, cx + dx, cy + dy, colour);
+            }
+
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    fn fill_circle(&mut self, (cx, cy): (isize, isize), radius: usize, colour: Rgb) {
+        self.touch(circle_bounds(self.bounds(), cx, cy, radius));
+
+        let radius = radius as isize;
+
+        for dy in -radius..=radius {
+            // The widest span on this row that stays inside the circle
+            let mut dx = radius;
+            while dx * dx + dy * dy > radius * radius {
+                dx -= 1;
+            }
+
+            for x in cx - dx..=cx + dx {
+                plot(self, x, cy + dy, colour);
+            }
+        }
+    }
+
+    /// Draws an image with its top left corner at (`x`, `y`), blending it by its alpha channel.
+    fn blit(&mut self, image: &Image, x: isize, y: isize) {
+        let area = clipped(self.bounds(), x, y, x + image.width() as isize, y + image.height() as isize);
+        self.touch(area);
+
+        for row in area.y..area.y + area.height {
+            for col in area.x..area.x + area.width {
+                let (colour, alpha) = image.pixel((col as isize - x) as usize, (row as isize - y) as usize);
+
+                match alpha {
+                    0 => {}
+                    255 => self.write_pixel(col, row, colour),
+                    alpha => blend(self, col, row, colour, alpha),
+                }
+            }
+        }
+    }
+}
+
+fn clip(bounds: Rect, x: isize, y: isize) -> Option<(usize, usize)> {
+    let x = usize::try_from(x).ok().filter(|&x| x < bounds.width)?;
+    let y = usize::try_from(y).ok().filter(|&y| y < bounds.height)?;
+
+    Some((x, y))
+}
+
+/// Sets a pixel for a shape that's already been `touch`ed.
+fn plot<D: Draw + ?Sized>(target: &mut D, x: isize, y: isize, colour: Rgb) {
+    if let Some((x, y)) = clip(target.bounds(), x, y) {
+        target.write_pixel(x, y, colour);
+    }
+}
+
+fn blend<D: Draw + ?Sized>(target: &mut D, x: usize, y: usize, colour: Rgb, alpha: u8) {
+    let under = target.pixel(x, y);
+    target.write_pixel(x, y, under.blend(colour, alpha));
+}
+
+/// The part of the area from (`left`, `top`) up to, but not including, (`right`, `bottom`)
+/// that's on the surface
+fn clipped(bounds: Rect, left: isize, top: isize, right: isize, bottom: isize) -> Rect {
+    let clamp = |value: isize| value.max(0) as usize;
+    let (left, top) = (clamp(left), clamp(top));
+
+    Rect::new(left, top, clamp(right).saturating_sub(left), clamp(bottom).saturating_sub(top)).intersection(bounds)
+}
+
+fn circle_bounds(bounds: Rect, cx: isize, cy: isize, radius: usize) -> Rect {
+    let radius = radius as isize;
+
+    clipped(bounds, cx - radius, cy - radius, cx + radius + 1, cy + radius + 1)
+}
+
+/// An image in memory to draw on.
+#[derive(Debug, Clone)]
+pub struct Surface {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+impl Surface {
+    pub fn new(width: usize, height: usize) -> Surface {
+        Surface {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// The pixels, row by row, as 0x00RRGGBB
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+}
+
+impl Draw for Surface {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Rgb {
+        Rgb::from_pixel(self.pixels[y * self.width + x])
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, colour: Rgb) {
+        self.pixels[y * self.width + x] = colour.pixel();
+    }
+}
+
+/// Past this many separate dirty rectangles, they're merged into one
+const MAX_DIRTY_RECTS: usize = 16;
+
+/// Draws into a `Surface` off screen, and keeps track of which parts changed so that `flush`
+/// only has to copy those.
+pub struct DoubleBuffer {
+    back: Surface,
+    dirty: Vec<Rect>,
+}
+
+impl DoubleBuffer {
+    pub fn new(width: usize, height: usize) -> DoubleBuffer {
+        DoubleBuffer {
+            back: Surface::new(width, height),
+            dirty: Vec::new(),
+        }
+    }
+
+    /// The rectangles that have changed since the last flush
+    pub fn dirty(&self) -> &[Rect] {
+        &self.dirty
+    }
+
+    /// Marks the whole buffer to be copied on the next flush.
+    pub fn invalidate(&mut self) {
+        self.dirty = vec![self.back.bounds()];
+    }
+
+    /// Copies the parts that changed to `front`, and then forgets about them.
+    pub fn flush(&mut self, front: &mut impl Draw) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        front.begin_frame();
+
+        for area in self.dirty.drain(..) {
+            let area = area.intersection(front.bounds());
+
+            for y in area.y..area.y + area.height {
+                for x in area.x..area.x + area.width {
+                    front.write_pixel(x, y, self.back.pixel(x, y));
+                }
+            }
+        }
+    }
+}
+
+impl Draw for DoubleBuffer {
+    fn width(&self) -> usize {
+        self.back.width
+    }
+
+    fn height(&self) -> usize {
+        self.back.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Rgb {
+        self.back.pixel(x, y)
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, colour: Rgb) {
+        self.back.write_pixel(x, y, colour);
+    }
+
+    fn touch(&mut self, area: Rect) {
+        if area.is_empty() {
+            return;
+        }
+
+        // Swallow every rectangle that overlaps, including ones that only overlap once it's grown,
+        // so that the rectangles stay apart and nothing's copied twice
+        let mut merged = area;
+        while let Some(index) = self.dirty.iter().position(|dirty| !dirty.intersection(merged).is_empty()) {
+            merged = merged.union(self.dirty.swap_remove(index));
+        }
+        self.dirty.push(merged);
+
+        if self.dirty.len() > MAX_DIRTY_RECTS {
+            let all = self.dirty.drain(..).fold(Rect::default(), Rect::union);
+            self.dirty.push(all);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb = Rgb::new(0xff, 0x00, 0x00);
+
+    /// The pixels that aren't black, row by row
+    fn lit(surface: &impl Draw) -> Vec<(usize, usize)> {
+        let mut lit = Vec::new();
+
+        for y in 0..surface.height() {
+            for x in 0..surface.width() {
+                if surface.pixel(x, y) != Rgb::BLACK {
+                    lit.push((x, y));
+                }
+            }
+        }
+
+        lit
+    }
+
+    #[test_case]
+    fn line_covers_both_ends_in_every_octant() {
+        let ends = [(16, 12), (12, 16), (4, 16), (0, 12), (0, 4), (4, 0), (12, 0), (16, 4), (8, 8)];
+
+        for (x1, y1) in ends {
+            let mut surface = Surface::new(17, 17);
+            surface.line((8, 8), (x1, y1), Rgb::WHITE);
+
+            let lit = lit(&surface);
+            let steps = (x1 - 8).unsigned_abs().max((y1 - 8).unsigned_abs());
+
+            assert!(lit.contains(&(8, 8)) && lit.contains(&(x1 as usize, y1 as usize)));
+            // One pixel for each step along the longer axis, and none left over
+            assert_eq!(lit.len(), steps + 1);
+        }
+    }
+
+    #[test_case]
+    fn line_off_the_edge_is_clipped() {
+        let mut surface = Surface::new(10, 10);
+        surface.line((-5, 5), (14, 5), Rgb::WHITE);
+        surface.line((3, -20), (3, -1), Rgb::WHITE);
+
+        assert_eq!(lit(&surface), (0..10).map(|x| (x, 5)).collect::<Vec<_>>());
+    }
+
+    #[test_case]
+    fn shapes_off_the_edge_are_clipped() {
+        let mut surface = Surface::new(8, 8);
+        surface.fill_rect(Rect::new(6, 6, 10, 10), Rgb::WHITE);
+        assert_eq!(lit(&surface), [(6, 6), (7, 6), (6, 7), (7, 7)]);
+
+        let mut surface = Surface::new(8, 8);
+        surface.fill_circle((0, 0), 2, Rgb::WHITE);
+        assert_eq!(lit(&surface), [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)]);
+
+        let mut surface = Surface::new(8, 8);
+        surface.circle((-3, -3), 2, Rgb::WHITE);
+        surface.blit(&Image::filled(4, 4, Rgb::WHITE, 255), 6, -2);
+        assert_eq!(lit(&surface), [(6, 0), (7, 0), (6, 1), (7, 1)]);
+    }
+
+    #[test_case]
+    fn blend_at_none_half_and_full_alpha() {
+        let under = Rgb::new(0x00, 0xff, 0x64);
+        let over = Rgb::new(0xff, 0x00, 0x64);
+
+        assert_eq!(under.blend(over, 0), under);
+        assert_eq!(under.blend(over, 128), Rgb::new(128, 127, 0x64));
+        assert_eq!(under.blend(over, 255), over);
+    }
+
+    #[test_case]
+    fn touch_merges_rectangles_that_overlap_once_grown() {
+        let mut buffer = DoubleBuffer::new(100, 100);
+
+        buffer.fill_rect(Rect::new(10, 15, 5, 5), RED);
+        buffer.fill_rect(Rect::new(0, 0, 2, 20), RED);
+        assert_eq!(buffer.dirty().len(), 2);
+
+        // Only overlaps the tall rectangle, but grown over it, it reaches the first one
+        buffer.fill_rect(Rect::new(0, 0, 12, 2), RED);
+        assert_eq!(buffer.dirty(), [Rect::new(0, 0, 15, 20)]);
+    }
+
+    #[test_case]
+    fn touch_collapses_too_many_rectangles() {
+        let mut buffer = DoubleBuffer::new(100, 100);
+
+        for x in 0..MAX_DIRTY_RECTS {
+            buffer.set_pixel(x as isize * 2, 0, RED);
+        }
+        assert_eq!(buffer.dirty().len(), MAX_DIRTY_RECTS);
+
+        buffer.set_pixel(0, 10, RED);
+        assert_eq!(buffer.dirty(), [Rect::new(0, 0, MAX_DIRTY_RECTS * 2 - 1, 11)]);
+    }
+
+    #[test_case]
+    fn flush_copies_what_changed() {
+        let mut buffer = DoubleBuffer::new(8, 8);
+        let mut front = Surface::new(8, 8);
+
+        buffer.line((0, 0), (7, 7), Rgb::WHITE);
+        buffer.flush(&mut front);
+
+        assert!(buffer.dirty().is_empty());
+        assert_eq!(lit(&front), (0..8).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+}
@@ -1,20 +1,36 @@
 use bootloader::BootInfo;
-use x86_64::structures::paging::OffsetPageTable;
+use x86_64::structures::paging::{OffsetPageTable, Page, Size4KiB};
 use x86_64::VirtAddr;
 
+use crate::acpi;
 use crate::allocator;
+use crate::config;
+#[cfg(feature = "framebuffer")]
+use crate::framebuffer;
 use crate::gdt;
+use crate::initrd;
 use crate::interrupts;
+use crate::logger;
 use crate::memory;
 use crate::memory::SprinkleFrameAllocator;
+use crate::percpu;
+use crate::ps2;
+use crate::smp;
+use crate::stack;
+use crate::task::keyboard;
+use crate::task::keymap::Layout;
+use crate::time;
+use crate::vt;
 
 pub unsafe fn init(
     boot_info: &'static BootInfo,
 ) -> (SprinkleFrameAllocator, OffsetPageTable<'static>) {
-    gdt::init_gdt();
-    interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
-    x86_64::instructions::interrupts::enable();
+    vt::init();
+    logger::init();
+
+    // Everything after this may consult the configuration, including the heap's size
+    config::init();
+    logger::set_level(None, config::get().log_level);
 
     let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
 
@@ -23,8 +39,58 @@ pub unsafe fn init(
         memory::page_table_init(physical_memory_offset),
     );
 
+    smp::reserve_trampoline(&mut frame_allocator);
+
+    // The bootloader leaves the first page of the kernel stack unmapped as its guard page
+    let kernel_stack_start =
+        Page::<Size4KiB>::containing_address(VirtAddr::new(stack::KERNEL_STACK_ADDRESS)) + 1;
+    stack::register_stack(stack::Stack {
+        name: "kernel stack",
+        bottom: kernel_stack_start.start_address(),
+        top: (kernel_stack_start + stack::KERNEL_STACK_PAGES).start_address(),
+    });
+
+    // The per-CPU tables live on the heap, so it has to come first
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("Failed to initialized the heap.");
+    logger::init_sinks();
+    initrd::init();
+
+    match Layout::load(config::get().keymap) {
+        Ok(layout) => keyboard::set_layout(layout),
+        Err(err) => log::warn!("Can't load the `{}` keymap ({err}); using US", config::get().keymap),
+    }
+
+    #[cfg(feature = "framebuffer")]
+    if let Err(err) = framebuffer::init(&mut mapper, &mut frame_allocator) {
+        log::warn!("Framebuffer unavailable ({err:?}); staying in text mode");
+    }
+
+    gdt::init_gdt(&mut mapper, &mut frame_allocator)
+        .expect("Failed to map the interrupt stacks.");
+    interrupts::init_idt();
+
+    if let Err(err) = acpi::init() {
+        log::warn!("ACPI unavailable ({err:?}); using the legacy PICs");
+    }
+
+    interrupts::init_controller(&mut mapper, &mut frame_allocator)
+        .expect("Failed to map the APIC registers.");
+
+    let apic_id = interrupts::apic::local_apic().map_or(0, |local_apic| local_apic.id());
+    percpu::init(0, apic_id);
+
+    x86_64::instructions::interrupts::enable();
+    time::init(&mut mapper, &mut frame_allocator)
+        .expect("Failed to map the HPET registers.");
+
+    keyboard::init();
+    if let Err(err) = ps2::init() {
+        log::warn!("PS/2 controller unavailable ({err:?}); no keyboard input");
+    }
+
+    smp::init(&mut mapper, &mut frame_allocator)
+        .expect("Failed to map the application processors' stacks.");
 
     (frame_allocator, mapper)
 }
@@ -0,0 +1,91 @@
+use crate::fs::{Path, FILESYSTEM};
+
+/// The `initrd` directory, packed by the build script
+static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.bin"));
+
+/// Unpacks the initrd into the filesystem. Needs the heap.
+pub fn init() {
+    let mut filesystem = FILESYSTEM.lock();
+    let mut files = files();
+
+    for (path, contents) in &mut files {
+        filesystem.create(Path::new(path), contents.to_vec());
+    }
+
+    if files.truncated {
+        log::warn!("The initrd is truncated; some files may be missing");
+    }
+}
+
+/// Looks a file up in the initrd itself, without the filesystem, so it works before the heap
+/// is set up. `path` is relative to `initrd/`.
+pub fn find(path: &str) -> Option<&'static [u8]> {
+    files().find(|&(name, _)| name == path).map(|(_, contents)| contents)
+}
+
+fn files() -> Files {
+    Files {
+        archive: INITRD,
+        done: false,
+        truncated: false,
+    }
+}
+
+/// The files in the archive, with their paths relative to `initrd/`
+struct Files {
+    archive: &'static [u8],
+    done: bool,
+    /// Set if the archive ended in the middle of a file
+    truncated: bool,
+}
+
+impl Iterator for Files {
+    type Item = (&'static str, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let file = self.next_file();
+
+        // Only the zero path length at the end finishes the archive cleanly
+        if file.is_none() && !self.done {
+            self.done = true;
+            self.truncated = true;
+        }
+
+        file
+    }
+}
+
+impl Files {
+    fn next_file(&mut self) -> Option<(&'static str, &'static [u8])> {
+        let path_len = take(&mut self.archive, 2)?;
+        let path_len = u16::from_le_bytes([path_len[0], path_len[1]]) as usize;
+
+        if path_len == 0 {
+            self.done = true;
+            return None;
+        }
+
+        let path = take(&mut self.archive, path_len)?;
+        let contents_len = take(&mut self.archive, 4)?;
+        let contents_len = u32::from_le_bytes(contents_len.try_into().unwrap()) as usize;
+        let contents = take(&mut self.archive, contents_len)?;
+
+        Some((core::str::from_utf8(path).unwrap_or_default(), contents))
+    }
+}
+
+/// Splits `len` bytes off the front of the archive.
+fn take<'a>(archive: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if archive.len() < len {
+        return None;
+    }
+
+    let (taken, rest) = archive.split_at(len);
+    *archive = rest;
+
+    Some(taken)
+}
@@ -1,105 +0,0 @@
-use crate::gdt;
-use core::fmt::Write;
-use crate::vga_buffer::global_writer;
-
-use x86_64::instructions::port::Port;
-use x86_64::structures::idt::PageFaultErrorCode;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-
-use pic8259::ChainedPics;
-
-use spin::Mutex;
-
-use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
-
-lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-        Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-    );
-}
-
-pub const PIC_1_OFFSET: u8 = 32;
-pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
-
-pub static PICS: Mutex<ChainedPics> =
-    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt[InterruptIndex::Timer.into()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.into()].set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault
-                .set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt
-    };
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
-}
-
-impl Into<u8> for InterruptIndex {
-    fn into(self) -> u8 {
-        self as u8
-    }
-}
-
-impl Into<usize> for InterruptIndex {
-    fn into(self) -> usize {
-        self as usize
-    }
-}
-
-pub fn init_idt() {
-    IDT.load();
-}
-
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.into());
-    }
-}
-
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    writeln!(global_writer::maybe(), "EXCEPTION BREAKPOINT:\n{stack_frame:#?}").ok();
-}
-
-extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, errno: u64) -> ! {
-    panic!("FATAL DOUBLE-FAULT:\n{stack_frame:?}\n\nERRNO: {errno}");
-}
-
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    let accessed_addr = Cr2::read();
-
-    panic!("FATAL PAGE FAULT\n{stack_frame:#?}\nACCESSED ADDR: {accessed_addr:#?}\nERRCODE: {error_code:#?}")
-}
-
-extern "x86-interrupt" fn keyboard_interrupt_handler(_: InterruptStackFrame) {
-    let mut port = Port::new(0x60);
-
-    let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
-
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.into());
-    }
-}
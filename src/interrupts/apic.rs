@@ -0,0 +1,264 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::ptr;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
+use x86_64::VirtAddr;
+
+use super::{InterruptIndex, TIMER_HZ};
+use crate::acpi::Madt;
+use crate::memory;
+
+/// Local APIC register offsets
+mod reg {
+    pub const ID: u32 = 0x20;
+    pub const TASK_PRIORITY: u32 = 0x80;
+    pub const EOI: u32 = 0xb0;
+    pub const SPURIOUS: u32 = 0xf0;
+    pub const ICR_LOW: u32 = 0x300;
+    pub const ICR_HIGH: u32 = 0x310;
+    pub const LVT_TIMER: u32 = 0x320;
+    pub const TIMER_INITIAL: u32 = 0x380;
+    pub const TIMER_CURRENT: u32 = 0x390;
+    pub const TIMER_DIVIDE: u32 = 0x3e0;
+}
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_MASKED: u32 = 1 << 16;
+/// Divide the timer's input clock by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// The local APIC of whichever CPU accesses it. Every CPU sees its own APIC at the same address.
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+/// An I/O APIC, which routes external interrupts to local APICs
+pub struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+}
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: Mutex<Vec<IoApic>> = Mutex::new(Vec::new());
+/// How many local APIC timer ticks (at the /16 divider) make up one kernel tick
+static TIMER_INITIAL_COUNT: OnceCell<u32> = OnceCell::uninit();
+
+impl LocalApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe { ptr::read_volatile((self.base + reg as u64).as_ptr()) }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe { ptr::write_volatile((self.base + reg as u64).as_mut_ptr(), value) }
+    }
+
+    /// The APIC ID of the current CPU
+    pub fn id(&self) -> u8 {
+        (self.read(reg::ID) >> 24) as u8
+    }
+
+    /// Software-enables the APIC and accepts every interrupt priority.
+    fn enable(&self) {
+        self.write(reg::TASK_PRIORITY, 0);
+        self.write(
+            reg::SPURIOUS,
+            APIC_SOFTWARE_ENABLE | InterruptIndex::Spurious as u32,
+        );
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(reg::EOI, 0);
+    }
+
+    /// Sends an inter-processor interrupt. `command` is the low half of the ICR.
+    pub fn send_ipi(&self, apic_id: u8, command: u32) {
+        self.write(reg::ICR_HIGH, (apic_id as u32) << 24);
+        self.write(reg::ICR_LOW, command);
+
+        // Wait for the delivery status bit to clear
+        while self.read(reg::ICR_LOW) & (1 << 12) != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Measures how many timer ticks pass in one kernel tick, using the PIT as a reference.
+    fn calibrate_timer(&self) -> u32 {
+        const PIT_FREQUENCY: u32 = 1_193_182;
+
+        let mut control: Port<u8> = Port::new(0x61);
+        let mut command: Port<u8> = Port::new(0x43);
+        let mut channel_2: Port<u8> = Port::new(0x42);
+
+        let pit_count = PIT_FREQUENCY / TIMER_HZ;
+
+        unsafe {
+            // Enable the channel 2 gate, but keep the speaker off
+            let value = control.read();
+            control.write((value & !0b10) | 0b01);
+
+            // Channel 2, low then high byte, mode 0 (interrupt on terminal count)
+            command.write(0b1011_0000);
+            channel_2.write(pit_count as u8);
+            channel_2.write((pit_count >> 8) as u8);
+
+            // Restart the countdown by toggling the gate
+            let value = control.read();
+            control.write(value & !0b01);
+            control.write(value | 0b01);
+        }
+
+        self.write(reg::TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(reg::LVT_TIMER, LVT_MASKED);
+        self.write(reg::TIMER_INITIAL, u32::MAX);
+
+        // Bit 5 goes high once the PIT has counted down
+        while unsafe { control.read() } & 0b10_0000 == 0 {
+            core::hint::spin_loop();
+        }
+
+        let elapsed = u32::MAX - self.read(reg::TIMER_CURRENT);
+        self.write(reg::TIMER_INITIAL, 0);
+
+        elapsed
+    }
+
+    /// Starts the timer in periodic mode, firing `InterruptIndex::Timer` every kernel tick.
+    fn start_timer(&self, initial_count: u32) {
+        self.write(reg::TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(reg::LVT_TIMER, TIMER_PERIODIC | InterruptIndex::Timer as u32);
+        self.write(reg::TIMER_INITIAL, initial_count);
+    }
+}
+
+impl IoApic {
+    const IOREGSEL: u64 = 0x00;
+    const IOWIN: u64 = 0x10;
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + Self::IOREGSEL).as_mut_ptr(), reg);
+            ptr::read_volatile((self.base + Self::IOWIN).as_ptr())
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + Self::IOREGSEL).as_mut_ptr(), reg);
+            ptr::write_volatile((self.base + Self::IOWIN).as_mut_ptr(), value);
+        }
+    }
+
+    /// The number of interrupt inputs that this I/O APIC has
+    fn redirection_entries(&self) -> u32 {
+        ((self.read(0x01) >> 16) & 0xff) + 1
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.redirection_entries()).contains(&gsi)
+    }
+
+    /// Points the interrupt input `gsi` at `vector` on the CPU with the given APIC ID.
+    fn set_redirection(&self, gsi: u32, vector: u8, apic_id: u8, active_low: bool, level_triggered: bool) {
+        let index = gsi - self.gsi_base;
+
+        // Fixed delivery, physical destination, unmasked
+        let mut low = vector as u32;
+        if active_low {
+            low |= 1 << 13;
+        }
+        if level_triggered {
+            low |= 1 << 15;
+        }
+
+        self.write(0x10 + index * 2 + 1, (apic_id as u32) << 24);
+        self.write(0x10 + index * 2, low);
+    }
+
+    /// Masks every interrupt input.
+    fn mask_all(&self) {
+        for index in 0..self.redirection_entries() {
+            self.write(0x10 + index * 2, LVT_MASKED);
+        }
+    }
+}
+
+/// Maps the local APIC and I/O APICs described by the MADT, masks the legacy PICs, and starts
+/// the local APIC timer as the kernel tick.
+pub fn init(
+    madt: &Madt,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    if madt.legacy_pics {
+        // Remap the PICs first so that any interrupt that's already pending lands on a vector
+        // that isn't a CPU exception, then mask everything.
+        let mut pics = super::PICS.lock();
+        unsafe {
+            pics.initialize();
+            pics.disable();
+        }
+    }
+
+    let base = memory::map_mmio(madt.local_apic_address, 4096, mapper, frame_allocator)?;
+    let local_apic = LOCAL_APIC.get_or_init(|| LocalApic { base });
+
+    let mut io_apics = IO_APICS.lock();
+
+    for info in &madt.io_apics {
+        let base = memory::map_mmio(info.address, 4096, mapper, frame_allocator)?;
+        let io_apic = IoApic {
+            base,
+            gsi_base: info.gsi_base,
+        };
+
+        io_apic.mask_all();
+        io_apics.push(io_apic);
+    }
+
+    local_apic.enable();
+
+    let initial_count = *TIMER_INITIAL_COUNT.get_or_init(|| local_apic.calibrate_timer());
+    local_apic.start_timer(initial_count);
+
+    Ok(())
+}
+
+/// Enables the local APIC of an application processor and starts its timer. The bootstrap
+/// processor must have called `init` first.
+pub fn init_ap() {
+    let (Some(local_apic), Ok(initial_count)) = (local_apic(), TIMER_INITIAL_COUNT.try_get()) else {
+        return;
+    };
+
+    local_apic.enable();
+    local_apic.start_timer(*initial_count);
+}
+
+/// Routes the ISA interrupt `irq` to `vector` on the current CPU, following any overrides in
+/// the MADT.
+pub fn route_isa_irq(madt: &Madt, irq: u8, vector: InterruptIndex) {
+    let Some(local_apic) = local_apic() else {
+        return;
+    };
+
+    let routing = madt.isa_irq(irq);
+    let io_apics = IO_APICS.lock();
+
+    if let Some(io_apic) = io_apics.iter().find(|io_apic| io_apic.handles(routing.gsi)) {
+        io_apic.set_redirection(
+            routing.gsi,
+            vector.into(),
+            local_apic.id(),
+            routing.active_low,
+            routing.level_triggered,
+        );
+    }
+}
+
+/// Returns the local APIC, if the APIC has been initialized.
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try_get().ok()
+}
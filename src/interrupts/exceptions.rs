@@ -0,0 +1,403 @@
+use core::arch::asm;
+use core::fmt::{self, Write};
+
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{
+    DescriptorTable, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    SelectorErrorCode,
+};
+
+use crate::backtrace::{self, Backtrace};
+use crate::gdt;
+use crate::stack;
+use crate::vga_buffer::global_writer;
+
+/// Installs a handler for every architectural exception (vectors 0-31).
+pub fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception.set_handler_fn(control_protection_handler);
+    idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+
+    unsafe {
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+}
+
+/// The register state at the time of an exception: the interrupt frame pushed by the CPU, plus
+/// the control registers.
+struct Registers<'a>(&'a InterruptStackFrame);
+
+impl fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.0;
+
+        writeln!(
+            f,
+            "RIP: {:#018x}  CS: {:#06x}  RFLAGS: {:#010x}",
+            frame.instruction_pointer.as_u64(),
+            frame.code_segment,
+            frame.cpu_flags
+        )?;
+        writeln!(
+            f,
+            "RSP: {:#018x}  SS: {:#06x}",
+            frame.stack_pointer.as_u64(),
+            frame.stack_segment
+        )?;
+        writeln!(
+            f,
+            "CR0: {:#010x}  CR2: {:#018x}  CR3: {:#010x}  CR4: {:#010x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read_raw().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+/// Decodes the error code that segment-related exceptions (#TS, #NP, #SS, #GP) push.
+struct Selector(u64);
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "not segment related");
+        }
+
+        let Some(code) = SelectorErrorCode::new(self.0) else {
+            return write!(f, "malformed selector error code {:#x}", self.0);
+        };
+
+        let table = match code.descriptor_table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+
+        write!(f, "{table} entry {}", code.index())?;
+
+        if code.external() {
+            write!(f, " (external event)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The floating-point exceptions, in the order of their flag and mask bits in the x87 status and
+/// control words and in MXCSR
+const FP_EXCEPTIONS: [&str; 6] = [
+    "invalid operation",
+    "denormal operand",
+    "divide by zero",
+    "overflow",
+    "underflow",
+    "precision",
+];
+
+/// Decodes the floating-point exceptions that have been raised, and which ones are masked. The
+/// ones that are raised but not masked caused the fault.
+struct FpExceptions {
+    flags: u32,
+    masks: u32,
+}
+
+impl fmt::Display for FpExceptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, bits: u32| -> fmt::Result {
+            let mut names = FP_EXCEPTIONS.iter().enumerate().filter(|(bit, _)| bits & 1 << bit != 0);
+
+            match names.next() {
+                None => write!(f, "none"),
+                Some((_, first)) => {
+                    write!(f, "{first}")?;
+                    names.try_for_each(|(_, name)| write!(f, ", {name}"))
+                }
+            }
+        };
+
+        write!(f, "caused by: ")?;
+        list(f, self.flags & !self.masks & 0x3f)?;
+        write!(f, "\nraised: ")?;
+        list(f, self.flags & 0x3f)?;
+        write!(f, "\nmasked: ")?;
+        list(f, self.masks & 0x3f)
+    }
+}
+
+/// Reports an exception that the kernel can recover from, then returns to the interrupted code.
+fn report(name: &str, stack_frame: &InterruptStackFrame, detail: fmt::Arguments) {
+    writeln!(
+        global_writer::maybe(),
+        "EXCEPTION {name}: {detail}\n{}",
+        Registers(stack_frame)
+    )
+    .ok();
+}
+
+/// Keeps a backtrace of the code an exception interrupted, for the panic handler. Has to be
+/// inlined into the exception handler: the handler saves the interrupted `rbp` at the bottom of
+/// its frame like any other function, but the rest of its frame isn't a normal call frame.
+#[inline(always)]
+fn record_backtrace(stack_frame: &InterruptStackFrame) {
+    let rbp: u64;
+    unsafe { asm!("mov {}, [rbp]", out(reg) rbp, options(nostack, readonly, preserves_flags)) };
+
+    backtrace::set_exception(Backtrace::from_exception(stack_frame, rbp));
+}
+
+/// Panics with a description of an exception that the kernel can't recover from.
+#[inline(always)]
+fn fatal(name: &str, stack_frame: &InterruptStackFrame, detail: fmt::Arguments) -> ! {
+    record_backtrace(stack_frame);
+
+    panic!("FATAL {name}: {detail}\n{}", Registers(stack_frame))
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    fatal("#DE DIVIDE ERROR", &stack_frame, format_args!("division by zero or quotient overflow"))
+}
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    use x86_64::registers::debug::Dr6;
+
+    report("#DB DEBUG", &stack_frame, format_args!("DR6: {:?}", Dr6::read()))
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    // System control port B reports whether the NMI came from a memory parity error (bit 7)
+    // or an I/O channel check (bit 6).
+    let status: u8 = unsafe { Port::new(0x61).read() };
+
+    report(
+        "NMI",
+        &stack_frame,
+        format_args!(
+            "parity error: {}, I/O channel check: {}",
+            status & 0x80 != 0,
+            status & 0x40 != 0
+        ),
+    )
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    writeln!(global_writer::maybe(), "EXCEPTION BREAKPOINT:\n{stack_frame:#?}").ok();
+}
+
+extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    // #OF is a trap, so the `into` instruction has already completed; carry on after it.
+    report("#OF OVERFLOW", &stack_frame, format_args!("INTO with the overflow flag set"))
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    fatal("#BR BOUND RANGE EXCEEDED", &stack_frame, format_args!("BOUND index out of range"))
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let rip = stack_frame.instruction_pointer;
+
+    fatal(
+        "#UD INVALID OPCODE",
+        &stack_frame,
+        format_args!("undefined or reserved instruction at {:#x}", rip.as_u64()),
+    )
+}
+
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    fatal(
+        "#NM DEVICE NOT AVAILABLE",
+        &stack_frame,
+        format_args!("FPU/SSE instruction used while unavailable (CR0: {:?})", Cr0::read()),
+    )
+}
+
+extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, errno: u64) -> ! {
+    // If the kernel stack overflows while the page fault handler can't be reached, the CPU
+    // escalates straight to a double fault, but CR2 still holds the guard page address.
+    record_backtrace(&stack_frame);
+
+    if let Some(stack_name) = stack::guard_page_owner(Cr2::read()) {
+        panic!("FATAL DOUBLE-FAULT: stack overflow in {stack_name}\n{stack_frame:#?}");
+    }
+
+    panic!("FATAL DOUBLE-FAULT:\n{stack_frame:?}\n\nERRNO: {errno}");
+}
+
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal("#TS INVALID TSS", &stack_frame, format_args!("{}", Selector(error_code)))
+}
+
+extern "x86-interrupt" fn segment_not_present_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal("#NP SEGMENT NOT PRESENT", &stack_frame, format_args!("{}", Selector(error_code)))
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal("#SS STACK-SEGMENT FAULT", &stack_frame, format_args!("{}", Selector(error_code)))
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal("#GP GENERAL PROTECTION FAULT", &stack_frame, format_args!("{}", Selector(error_code)))
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let accessed_addr = Cr2::read();
+    record_backtrace(&stack_frame);
+
+    if let Some(stack_name) = stack::guard_page_owner(accessed_addr) {
+        panic!("FATAL PAGE FAULT: stack overflow in {stack_name}\n{stack_frame:#?}\nACCESSED ADDR: {accessed_addr:#?}")
+    }
+
+    panic!("FATAL PAGE FAULT\n{stack_frame:#?}\nACCESSED ADDR: {accessed_addr:#?}\nERRCODE: {error_code:#?}")
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    const STACK_FAULT: u16 = 1 << 6;
+    /// Set on a stack fault if the stack overflowed, clear if it underflowed
+    const C1: u16 = 1 << 9;
+
+    let status: u16;
+    let mut control: u16 = 0;
+    unsafe {
+        core::arch::asm!("fnstsw ax", out("ax") status, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("fnstcw [{}]", in(reg) &mut control, options(nostack, preserves_flags));
+    }
+
+    let exceptions = FpExceptions {
+        flags: status as u32,
+        masks: control as u32,
+    };
+    let stack_fault = match (status & STACK_FAULT != 0, status & C1 != 0) {
+        (false, _) => "",
+        (true, true) => "\nx87 register stack overflow",
+        (true, false) => "\nx87 register stack underflow",
+    };
+
+    fatal(
+        "#MF x87 FLOATING-POINT ERROR",
+        &stack_frame,
+        format_args!("FSW: {status:#06x}  FCW: {control:#06x}\n{exceptions}{stack_fault}"),
+    )
+}
+
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) {
+    fatal(
+        "#AC ALIGNMENT CHECK",
+        &stack_frame,
+        format_args!("unaligned memory access with alignment checking enabled"),
+    )
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    use x86_64::registers::model_specific::Msr;
+
+    const IA32_MCG_STATUS: u32 = 0x17a;
+
+    let status = unsafe { Msr::new(IA32_MCG_STATUS).read() };
+
+    // Bit 0 (RIPV) says whether execution can restart at RIP, but the error itself is already
+    // lost, so there is nothing sensible to restart.
+    fatal(
+        "#MC MACHINE CHECK",
+        &stack_frame,
+        format_args!(
+            "MCG_STATUS: {status:#x} (restart IP valid: {}, error IP valid: {})",
+            status & 1 != 0,
+            status & 2 != 0
+        ),
+    )
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    let mut mxcsr: u32 = 0;
+    unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr, options(nostack, preserves_flags)) };
+
+    // The flags are bits 0-5, and their masks bits 7-12
+    let exceptions = FpExceptions {
+        flags: mxcsr,
+        masks: mxcsr >> 7,
+    };
+
+    fatal(
+        "#XM SIMD FLOATING-POINT EXCEPTION",
+        &stack_frame,
+        format_args!("MXCSR: {mxcsr:#010x}\n{exceptions}"),
+    )
+}
+
+extern "x86-interrupt" fn virtualization_handler(stack_frame: InterruptStackFrame) {
+    fatal("#VE VIRTUALIZATION EXCEPTION", &stack_frame, format_args!("EPT violation"))
+}
+
+extern "x86-interrupt" fn control_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let cause = match error_code & 0x7fff {
+        1 => "near RET",
+        2 => "far RET or IRET",
+        3 => "missing ENDBRANCH",
+        4 => "RSTORSSP",
+        5 => "SETSSBSY",
+        _ => "unknown cause",
+    };
+
+    fatal("#CP CONTROL PROTECTION", &stack_frame, format_args!("{cause}"))
+}
+
+extern "x86-interrupt" fn hv_injection_handler(stack_frame: InterruptStackFrame) {
+    fatal("#HV HYPERVISOR INJECTION", &stack_frame, format_args!("injected by the hypervisor"))
+}
+
+extern "x86-interrupt" fn vmm_communication_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal("#VC VMM COMMUNICATION", &stack_frame, format_args!("exit code {error_code:#x}"))
+}
+
+extern "x86-interrupt" fn security_exception_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal("#SX SECURITY EXCEPTION", &stack_frame, format_args!("error code {error_code:#x}"))
+}
@@ -0,0 +1,187 @@
+pub mod apic;
+mod exceptions;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
+
+use crate::acpi;
+use crate::percpu;
+
+use pic8259::ChainedPics;
+
+use spin::Mutex;
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// How many times per second the timer interrupt fires
+pub const TIMER_HZ: u32 = 100;
+
+/// The legacy PICs. Only used to deliver interrupts if the firmware doesn't describe any APICs.
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Whether interrupts are delivered through the APIC rather than the legacy PICs
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The number of timer interrupts since boot, as counted by the bootstrap processor
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        exceptions::set_handlers(&mut idt);
+        idt[InterruptIndex::Timer.into()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.into()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.into()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::Spurious.into()].set_handler_fn(spurious_interrupt_handler);
+        idt
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+    Mouse = PIC_1_OFFSET + 12,
+    /// Raised by the local APIC when an interrupt disappears before it can be delivered
+    Spurious = 0xff,
+}
+
+impl Into<u8> for InterruptIndex {
+    fn into(self) -> u8 {
+        self as u8
+    }
+}
+
+impl Into<usize> for InterruptIndex {
+    fn into(self) -> usize {
+        self as usize
+    }
+}
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// Sets up interrupt delivery: through the local APIC and I/O APICs if the ACPI tables describe
+/// them, otherwise through the legacy PICs. Needs ACPI to be initialized.
+pub fn init_controller(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let Some(madt) = acpi::madt().filter(|madt| !madt.io_apics.is_empty()) else {
+        unsafe {
+            let mut pics = PICS.lock();
+            pics.initialize();
+
+            // The firmware may have left the mouse (IRQ 12), or the cascade to the second PIC
+            // that it comes through (IRQ 2), masked
+            let [master, slave] = pics.read_masks();
+            pics.write_masks(master & !(1 << 2), slave & !(1 << 4));
+        }
+        start_pit();
+        return Ok(());
+    };
+
+    apic::init(&madt, mapper, frame_allocator)?;
+    apic::route_isa_irq(&madt, 1, InterruptIndex::Keyboard);
+    apic::route_isa_irq(&madt, 12, InterruptIndex::Mouse);
+
+    APIC_ENABLED.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// Whether interrupts are delivered through the APIC
+pub fn apic_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::Acquire)
+}
+
+/// The number of timer ticks (at `TIMER_HZ`) since interrupts were enabled
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Busy-waits for at least `count` timer ticks. Interrupts must be enabled.
+pub fn wait_ticks(count: u64) {
+    // The first tick might come immediately, so wait for one extra
+    let target = ticks() + count + 1;
+
+    while ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Makes the PIT fire the timer interrupt `TIMER_HZ` times per second, for when there's no local
+/// APIC timer. The PIT's default rate is only about 18 Hz.
+fn start_pit() {
+    const PIT_FREQUENCY: u32 = 1_193_182;
+
+    let divisor = PIT_FREQUENCY / TIMER_HZ;
+
+    unsafe {
+        // Channel 0, low then high byte, mode 2 (rate generator)
+        Port::<u8>::new(0x43).write(0b0011_0100);
+
+        let mut channel_0: Port<u8> = Port::new(0x40);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Acknowledges the interrupt so that the controller can deliver the next one.
+pub fn end_of_interrupt(index: InterruptIndex) {
+    if apic_enabled() {
+        if let Some(local_apic) = apic::local_apic() {
+            local_apic.end_of_interrupt();
+        }
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(index.into()) };
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Every CPU has its own timer; only count the ticks of one of them
+    if percpu::current().cpu_id == 0 {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    end_of_interrupt(InterruptIndex::Timer);
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Spurious interrupts must not be acknowledged
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_: InterruptStackFrame) {
+    let mut port = Port::new(0x60);
+
+    // The interrupt can arrive after the byte was already read while setting up the controller
+    if crate::ps2::keyboard_byte_waiting() {
+        let scancode: u8 = unsafe { port.read() };
+
+        if !crate::ps2::take_response(scancode) {
+            crate::task::keyboard::add_scancode(scancode);
+        }
+    }
+
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_: InterruptStackFrame) {
+    let mut port = Port::new(0x60);
+
+    if crate::ps2::mouse_byte_waiting() {
+        let byte: u8 = unsafe { port.read() };
+        crate::task::mouse::add_byte(byte);
+    }
+
+    end_of_interrupt(InterruptIndex::Mouse);
+}
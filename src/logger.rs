@@ -0,0 +1,470 @@
+//! The kernel log, behind the `log` crate's macros.
+//!
+//! `log::warn!` and friends can be used anywhere, interrupt handlers included: a record is
+//! formatted on the stack and copied into a fixed ring of slots without taking any locks or
+//! allocating. The newest `RING_SLOTS` records stay there for `dmesg`. Sinks (the screen,
+//! serial and a file) are fed from a task, so that they're free to lock and allocate; records
+//! logged before it starts reach them once it does.
+//!
+//! Each target (the module a record comes from, without the crate name) can have its own
+//! level with `set_level`; everything else uses the default level.
+
+use core::fmt::{self, Write};
+use core::future::poll_fn;
+use core::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use futures_util::task::AtomicWaker;
+use log::{Level, LevelFilter, Log, Metadata};
+use spin::{Mutex, RwLock};
+
+use crate::fs::{Path, FILESYSTEM};
+use crate::percpu;
+use crate::serial;
+use crate::time;
+use crate::vga_buffer::{ColourCode, ColourText};
+use crate::vt;
+
+/// How many records the ring keeps
+const RING_SLOTS: usize = 256;
+/// Targets and messages are cut off at these lengths, in 8-byte words
+const TARGET_WORDS: usize = 4;
+const MESSAGE_WORDS: usize = 20;
+
+/// Log files are trimmed from the front once they grow past this
+const MAX_FILE_SIZE: usize = 64 * 1024;
+
+/// Removed from the start of targets, since every module is in it
+const CRATE_PREFIX: &str = "sprinkles_os::";
+
+/// One record in the ring. The fields are atomics so that a record can be read while another
+/// CPU (or an interrupt) overwrites it; `stamp` tells the reader whether that happened.
+struct Slot {
+    /// 0 while empty, odd while a record is being written, and otherwise `sequence * 2 + 2`
+    stamp: AtomicU64,
+    /// Nanoseconds since boot
+    timestamp: AtomicU64,
+    /// The level, the CPU and the lengths of the target and message, packed together
+    meta: AtomicU64,
+    target: [AtomicU64; TARGET_WORDS],
+    message: [AtomicU64; MESSAGE_WORDS],
+}
+
+impl Slot {
+    const fn new() -> Slot {
+        Slot {
+            stamp: AtomicU64::new(0),
+            timestamp: AtomicU64::new(0),
+            meta: AtomicU64::new(0),
+            target: [const { AtomicU64::new(0) }; TARGET_WORDS],
+            message: [const { AtomicU64::new(0) }; MESSAGE_WORDS],
+        }
+    }
+}
+
+static RING: [Slot; RING_SLOTS] = [const { Slot::new() }; RING_SLOTS];
+/// The sequence number of the next record
+static NEXT: AtomicU64 = AtomicU64::new(0);
+/// Woken whenever a record is finished, for the sinks
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+/// Levels for particular targets, and everything under them
+static TARGET_LEVELS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+
+static SINKS: Mutex<Vec<RegisteredSink>> = Mutex::new(Vec::new());
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// A record read back from the ring.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub sequence: u64,
+    pub timestamp: Duration,
+    pub cpu: usize,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl Record {
+    /// The colour that the record's level is shown in
+    pub fn colour(&self) -> ColourCode {
+        match self.level {
+            Level::Error => ColourCode(0x0c),
+            Level::Warn => ColourCode(0x0e),
+            Level::Info => ColourCode(0x0f),
+            Level::Debug | Level::Trace => ColourCode(0x07),
+        }
+    }
+
+    /// The record with its level in colour, ready for `Writer::write_colourful`
+    pub fn to_colour_text(&self) -> ColourText {
+        let (timestamp, level, rest) = self.parts();
+
+        ColourText::colour(ColourCode::default(), &timestamp)
+            .then(self.colour(), &level)
+            .then(ColourCode::default(), &rest)
+    }
+
+    fn parts(&self) -> (String, String, String) {
+        let timestamp = alloc::format!(
+            "[{:>5}.{:03}] ",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_millis()
+        );
+        let level = alloc::format!("{:<5}", self.level);
+        let rest = alloc::format!(" {}: {}", self.target, self.message);
+
+        (timestamp, level, rest)
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (timestamp, level, rest) = self.parts();
+
+        write!(f, "{timestamp}{level}{rest}")
+    }
+}
+
+/// Somewhere that records are copied to as they're logged.
+pub trait Sink: Send {
+    fn write(&mut self, record: &Record);
+}
+
+struct RegisteredSink {
+    name: &'static str,
+    level: LevelFilter,
+    sink: Box<dyn Sink>,
+}
+
+/// Writes records to a virtual terminal.
+pub struct ScreenSink {
+    pub vt: usize,
+}
+
+impl Sink for ScreenSink {
+    fn write(&mut self, record: &Record) {
+        let mut screen = vt::lock(self.vt);
+
+        screen.write_colourful(&record.to_colour_text());
+        screen.write_string("\n");
+    }
+}
+
+/// Writes records to the first serial port.
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn write(&mut self, record: &Record) {
+        writeln!(serial::lock(), "{record}").ok();
+    }
+}
+
+/// Appends records to a file in the filesystem, dropping the oldest ones once it's full.
+pub struct FileSink {
+    pub path: &'static str,
+}
+
+impl Sink for FileSink {
+    fn write(&mut self, record: &Record) {
+        let line = alloc::format!("{record}\n");
+        let mut filesystem = FILESYSTEM.lock();
+        let file = filesystem.append(Path::new(self.path), line.as_bytes());
+
+        let contents = file.contents();
+        if contents.len() > MAX_FILE_SIZE {
+            // Cut off whole lines from the front, down to half the limit, so this only happens
+            // once in a while
+            let excess = contents.len() - MAX_FILE_SIZE / 2;
+            let cut = contents[excess..].iter().position(|&byte| byte == b'\n').map_or(contents.len(), |end| excess + end + 1);
+            file.remove_front(cut);
+        }
+    }
+}
+
+/// Collects formatted text into a fixed buffer, cutting it off at a character boundary once
+/// the buffer's full.
+struct Truncating<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Truncating<N> {
+    fn new() -> Self {
+        Truncating { bytes: [0; N], len: 0 }
+    }
+}
+
+impl<const N: usize> Write for Truncating<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut fits = s.len().min(N - self.len);
+        while !s.is_char_boundary(fits) {
+            fits -= 1;
+        }
+
+        self.bytes[self.len..self.len + fits].copy_from_slice(&s.as_bytes()[..fits]);
+        self.len += fits;
+
+        Ok(())
+    }
+}
+
+fn store_bytes(words: &[AtomicU64], bytes: &[u8]) {
+    for (word, chunk) in words.iter().zip(bytes.chunks(8)) {
+        let mut buffer = [0; 8];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        word.store(u64::from_le_bytes(buffer), Ordering::Relaxed);
+    }
+}
+
+fn load_bytes(words: &[AtomicU64], len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.load(Ordering::Relaxed).to_le_bytes()).collect();
+    bytes.truncate(len);
+    bytes
+}
+
+fn level_from_index(index: u64) -> Level {
+    match index {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+fn level_filter_from_index(index: usize) -> LevelFilter {
+    match index {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// The target of a module path, without the crate name
+fn short_target(target: &str) -> &str {
+    target.strip_prefix(CRATE_PREFIX).unwrap_or(target)
+}
+
+/// Copies a record into the ring.
+fn push(level: Level, target: &str, message: &[u8]) {
+    let sequence = NEXT.fetch_add(1, Ordering::Relaxed);
+    let slot = &RING[sequence as usize % RING_SLOTS];
+
+    let target = &target.as_bytes()[..target.len().min(TARGET_WORDS * 8)];
+    let cpu = percpu::try_current().map_or(0, |percpu| percpu.cpu_id);
+    let meta = level as u64 | (cpu as u64 & 0xff) << 8 | (target.len() as u64) << 16 | (message.len() as u64) << 32;
+
+    slot.stamp.store(sequence * 2 + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    slot.timestamp.store(time::uptime().as_nanos() as u64, Ordering::Relaxed);
+    slot.meta.store(meta, Ordering::Relaxed);
+    store_bytes(&slot.target, target);
+    store_bytes(&slot.message, message);
+
+    slot.stamp.store(sequence * 2 + 2, Ordering::Release);
+    WAKER.wake();
+}
+
+/// Whether a record has been written, or already overwritten. A sequence number is handed out
+/// before its slot is claimed, so until then the slot still has the old record's stamp.
+fn finished(sequence: u64) -> bool {
+    let stamp = RING[sequence as usize % RING_SLOTS].stamp.load(Ordering::Acquire);
+
+    sequence < NEXT.load(Ordering::Acquire) && stamp >= sequence * 2 + 2
+}
+
+enum Read {
+    Done(Record),
+    /// Not started yet, or still being written
+    Writing,
+    /// Overwritten by a newer record
+    Gone,
+}
+
+fn read(sequence: u64) -> Read {
+    let slot = &RING[sequence as usize % RING_SLOTS];
+    let stamp = slot.stamp.load(Ordering::Acquire);
+
+    if stamp <= sequence * 2 + 1 {
+        return Read::Writing;
+    } else if stamp != sequence * 2 + 2 {
+        return Read::Gone;
+    }
+
+    let timestamp = slot.timestamp.load(Ordering::Relaxed);
+    let meta = slot.meta.load(Ordering::Relaxed);
+    let target = load_bytes(&slot.target, (meta >> 16 & 0xffff) as usize);
+    let message = load_bytes(&slot.message, (meta >> 32) as usize);
+
+    // If a writer started on the slot in the meantime, what was read is a mess
+    fence(Ordering::Acquire);
+    if slot.stamp.load(Ordering::Relaxed) != stamp {
+        return Read::Gone;
+    }
+
+    Read::Done(Record {
+        sequence,
+        timestamp: Duration::from_nanos(timestamp),
+        cpu: (meta >> 8 & 0xff) as usize,
+        level: level_from_index(meta & 0xff),
+        target: String::from_utf8_lossy(&target).to_string(),
+        message: String::from_utf8_lossy(&message).to_string(),
+    })
+}
+
+/// Every record still in the ring, oldest first
+pub fn records() -> Vec<Record> {
+    let next = NEXT.load(Ordering::Acquire);
+    let first = next.saturating_sub(RING_SLOTS as u64);
+
+    (first..next)
+        .filter_map(|sequence| match read(sequence) {
+            Read::Done(record) => Some(record),
+            Read::Writing | Read::Gone => None,
+        })
+        .collect()
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level(short_target(metadata.target()))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut message = Truncating::<{ MESSAGE_WORDS * 8 }>::new();
+        write!(message, "{}", record.args()).ok();
+
+        push(record.level(), short_target(record.target()), &message.bytes[..message.len]);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger. It doesn't need the heap, so records can be logged from the very start
+/// of boot, but they only reach the sinks once `init_sinks` has added them and `run_sinks` is
+/// running.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        // Filtering happens per target, in the logger
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Adds the screen, serial and file sinks. Needs the heap.
+pub fn init_sinks() {
+    add_sink("screen", LevelFilter::Warn, ScreenSink { vt: 0 });
+    add_sink("serial", LevelFilter::Info, SerialSink);
+    add_sink("file", LevelFilter::Debug, FileSink { path: "/log/kernel" });
+}
+
+/// Adds a sink for records at `level` or more severe.
+pub fn add_sink(name: &'static str, level: LevelFilter, sink: impl Sink + 'static) {
+    SINKS.lock().push(RegisteredSink {
+        name,
+        level,
+        sink: Box::new(sink),
+    });
+}
+
+/// The names and levels of the sinks
+pub fn sinks() -> Vec<(&'static str, LevelFilter)> {
+    SINKS.lock().iter().map(|sink| (sink.name, sink.level)).collect()
+}
+
+/// The level that records from `target` are logged at
+pub fn level(target: &str) -> LevelFilter {
+    let default = level_filter_from_index(DEFAULT_LEVEL.load(Ordering::Relaxed));
+
+    // The levels might be being changed on this CPU, if this is an interrupt handler
+    let Some(levels) = TARGET_LEVELS.try_read() else {
+        return default;
+    };
+
+    levels
+        .iter()
+        .filter(|(prefix, _)| {
+            target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(default, |(_, level)| *level)
+}
+
+/// Sets the level of a target and the modules under it, or the default level if `target` is
+/// `None`.
+pub fn set_level(target: Option<&str>, level: LevelFilter) {
+    let Some(target) = target else {
+        DEFAULT_LEVEL.store(level as usize, Ordering::Relaxed);
+        return;
+    };
+
+    let mut levels = TARGET_LEVELS.write();
+
+    match levels.iter_mut().find(|(prefix, _)| prefix == target) {
+        Some((_, existing)) => *existing = level,
+        None => levels.push((target.to_string(), level)),
+    }
+}
+
+/// Passes records to the sinks as they're logged. Records that are overwritten before they can
+/// be passed on are counted, and reported with the next one.
+pub async fn run_sinks() {
+    let mut delivered = 0;
+    let mut lost = 0;
+
+    loop {
+        poll_fn(|cx| {
+            if finished(delivered) {
+                return Poll::Ready(());
+            }
+
+            WAKER.register(cx.waker());
+
+            match finished(delivered) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await;
+
+        while delivered < NEXT.load(Ordering::Acquire) {
+            let mut record = match read(delivered) {
+                Read::Done(record) => record,
+                // It'll wake this task when it's done
+                Read::Writing => break,
+                Read::Gone => {
+                    lost += 1;
+                    delivered += 1;
+                    continue;
+                }
+            };
+
+            if lost > 0 {
+                record.message = alloc::format!("{} ({lost} earlier records lost)", record.message);
+                lost = 0;
+            }
+
+            for sink in SINKS.lock().iter_mut().filter(|sink| record.level <= sink.level) {
+                sink.sink.write(&record);
+            }
+
+            delivered += 1;
+        }
+    }
+}
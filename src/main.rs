@@ -9,35 +9,68 @@
 #![no_std]
 #![no_main]
 #![allow(dead_code)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 #[macro_use(vec)]
 extern crate alloc;
 
+mod acpi;
 mod allocator;
+mod backtrace;
+mod config;
+mod font;
+#[cfg(feature = "framebuffer")]
+mod framebuffer;
 mod gdt;
+mod graphics;
 mod init;
+mod initrd;
 mod interrupts;
+mod logger;
 mod memory;
+mod pci;
+mod percpu;
+mod power;
+mod ps2;
 mod runtime;
+mod serial;
+mod smp;
+mod stack;
 mod task;
+mod time;
+mod tui;
+mod vt;
 pub mod vga_buffer;
 pub mod fs;
 
 use core::fmt::Write;
 use core::panic::PanicInfo;
 
-use alloc::boxed::Box;
+use alloc::format;
+use backtrace::Backtrace;
 use bootloader::{entry_point, BootInfo};
-use pc_keyboard::{DecodedKey};
-use runtime::{executor::Executor, Task};
+use runtime::{executor::Executor, Priority};
 use vga_buffer::{global_writer, ColourCode, ColourText};
 
 use vga_buffer::Colour::*;
 
-use crate::task::keyboard;
+use crate::task::{keyboard, mouse, shell};
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    let backtrace = match backtrace::take_exception() {
+        Some(backtrace) => backtrace,
+        None => Backtrace::capture(),
+    };
+
+    let mut serial = unsafe { serial::force_lock() };
+    writeln!(serial, "Kernel panic: {info:#}\n{backtrace}").ok();
+
+    if cfg!(test) {
+        power::exit_qemu(false);
+    }
+
     let mut display = unsafe { global_writer::force_lock() };
 
     let error_colour = ColourCode::new(White, Red);
@@ -49,6 +82,9 @@ fn panic(info: &PanicInfo) -> ! {
     write!(display, "Kernel panic: {info:#}")
         .expect("Panicked when displaying error message. You're all alone.");
 
+    // Only show as many frames as fit on screen; serial has the full backtrace.
+    write!(display, "\n\n{backtrace:.12}").ok();
+
     loop {
         x86_64::instructions::hlt();
     }
@@ -60,31 +96,74 @@ entry_point!(boot_init);
 fn boot_init(boot_info: &'static BootInfo) -> ! {
     unsafe { init::init(boot_info) };
 
+    #[cfg(test)]
+    test_main();
+
     let mut executor = Executor::new();
-    executor.spawn(Task::new(keyboard::handle_keypresses(
-        Box::new(print_key),
-    )));
+    runtime::Builder::new()
+        .name("keyboard")
+        .priority(Priority::Interactive)
+        .spawn(keyboard::handle_keypresses());
+
+    runtime::Builder::new()
+        .name("log")
+        .spawn(logger::run_sinks());
+
+    if config::get().mouse {
+        runtime::Builder::new()
+            .name("mouse")
+            .priority(Priority::Interactive)
+            .spawn(mouse::handle_mouse_events());
+    }
 
-    executor.spawn(Task::new(main()));
+    executor.spawn_named("main", main());
     executor.run();
 }
 
-// Placeholder functions to print each pressed key until a true handling system can be implemented
-pub fn print_key(key: DecodedKey) {
-    match key {
-        DecodedKey::RawKey(raw) => write!(global_writer::maybe(), "{raw:?}").ok(),
-        DecodedKey::Unicode(character) => write!(global_writer::maybe(), "{character}").ok(),
-    };
-}
-
 /// Main runtime
 pub async fn main() {
     let mut screen = global_writer::lock();
 
-    writeln!(screen, "{}", ColourText::colour(ColourCode(0x3f), "SprinklesOS")).ok();
-    writeln!(screen, 
-        "Authored by: {}",
-        ColourText::colour(ColourCode(0xdf), "[T-O-R-U-S]")
-    ).ok();
+    screen.write_colourful(&ColourText::colour(ColourCode(0x3f), "SprinklesOS\n"));
+    write!(screen, "Authored by: ").ok();
+    screen.write_colourful(&ColourText::colour(ColourCode(0xdf), "[T-O-R-U-S]\n"));
+    drop(screen);
+
+    // A shell on each of the first `shells` virtual terminals
+    for vt in 0..config::get().shells {
+        runtime::Builder::new()
+            .name(format!("shell (tty{})", vt + 1))
+            .priority(Priority::Interactive)
+            .console(vt)
+            .spawn(shell::run_shell());
+    }
+}
+
+
+/// A `#[test_case]`, which reports its own name.
+#[cfg(test)]
+trait Testable {
+    fn run(&self);
 }
 
+#[cfg(test)]
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        write!(serial::lock(), "{}... ", core::any::type_name::<T>()).ok();
+        self();
+        writeln!(serial::lock(), "ok").ok();
+    }
+}
+
+/// Runs the `#[test_case]`s once the kernel is set up, reporting on serial, and exits QEMU. A
+/// failing test panics, and the panic handler exits QEMU with an error instead.
+#[cfg(test)]
+fn test_runner(tests: &[&dyn Testable]) {
+    writeln!(serial::lock(), "Running {} tests", tests.len()).ok();
+
+    for test in tests {
+        test.run();
+    }
+
+    power::exit_qemu(true);
+}
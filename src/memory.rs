@@ -1,8 +1,20 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use x86_64::PhysAddr;
 use x86_64::structures::paging::{OffsetPageTable, PhysFrame, FrameAllocator, Size4KiB};
+use x86_64::structures::paging::{mapper::MapToError, Mapper, Page, PageTableFlags};
 use x86_64::{structures::paging::PageTable, VirtAddr};
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+
+/// Where the bootloader mapped all of physical memory
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// The start of the virtual memory region that memory-mapped devices are mapped into
+const MMIO_REGION_START: u64 = 0x_6666_0000_0000;
+
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_REGION_START);
 
 pub struct SprinkleFrameAllocator {
     memory_map: &'static MemoryMap,
@@ -39,10 +51,55 @@ unsafe impl FrameAllocator<Size4KiB> for SprinkleFrameAllocator {
 }
 
 pub unsafe fn page_table_init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
+
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Returns the address that `phys` can be accessed through in the complete physical memory
+/// mapping set up by the bootloader. Panics if paging hasn't been initialized yet.
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("Physical memory accessed before paging was initialized");
+
+    *offset + phys.as_u64()
+}
+
+/// Maps `size` bytes of device memory starting at `phys` as uncacheable, returning the virtual
+/// address that corresponds to `phys`. The physical memory mapping from the bootloader can't be
+/// used for this, since it's cached and might not cover device memory at all.
+pub fn map_mmio(
+    phys: PhysAddr,
+    size: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(phys + size.max(1) - 1u64);
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+
+    let start = NEXT_MMIO.fetch_add(
+        (frames.count() as u64) * Page::<Size4KiB>::SIZE,
+        Ordering::Relaxed,
+    );
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+
+    for (index, frame) in PhysFrame::range_inclusive(first_frame, last_frame).enumerate() {
+        let page = first_page + index as u64;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(first_page.start_address() + (phys - first_frame.start_address()))
+}
+
 // NOTE: Do not call this function more than once (to avoid aliasing the mutable reference)
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
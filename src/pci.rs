@@ -0,0 +1,105 @@
+//! PCI configuration space, through the legacy configuration ports.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Returned for the vendor ID of slots with nothing in them
+const NO_DEVICE: u16 = 0xffff;
+
+const VENDOR_ID: u8 = 0x00;
+const HEADER_TYPE: u8 = 0x0c;
+const BAR0: u8 = 0x10;
+
+/// Set in the header type of devices with more than one function
+const MULTI_FUNCTION: u32 = 1 << 23;
+
+/// The address and data ports, which have to be used together
+static CONFIG: Mutex<(Port<u32>, Port<u32>)> = Mutex::new((Port::new(CONFIG_ADDRESS), Port::new(CONFIG_DATA)));
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+}
+
+impl Device {
+    /// Reads a 32-bit register from the configuration space. `offset` is rounded down to a
+    /// multiple of 4.
+    pub fn read(&self, offset: u8) -> u32 {
+        let address = 1 << 31
+            | (self.bus as u32) << 16
+            | (self.slot as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xfc) as u32;
+
+        without_interrupts(|| {
+            let mut config = CONFIG.lock();
+
+            unsafe {
+                config.0.write(address);
+                config.1.read()
+            }
+        })
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(VENDOR_ID) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(VENDOR_ID) >> 16) as u16
+    }
+
+    /// The address that a memory BAR points to, or `None` if it's an I/O BAR or isn't set.
+    pub fn memory_bar(&self, index: u8) -> Option<PhysAddr> {
+        const IO_SPACE: u32 = 0x01;
+        const TYPE_64_BIT: u32 = 0x04;
+
+        let offset = BAR0 + index * 4;
+        let low = self.read(offset);
+
+        if low & IO_SPACE != 0 {
+            return None;
+        }
+
+        let high = match low & 0x06 {
+            TYPE_64_BIT => self.read(offset + 4) as u64,
+            _ => 0,
+        };
+        let address = high << 32 | (low & !0x0f) as u64;
+
+        (address != 0).then(|| PhysAddr::new(address))
+    }
+
+    fn exists(&self) -> bool {
+        self.vendor_id() != NO_DEVICE
+    }
+}
+
+/// Every device function on the bus, found by trying each slot.
+pub fn devices() -> impl Iterator<Item = Device> {
+    (0..=255u8)
+        .flat_map(|bus| (0..32u8).map(move |slot| Device { bus, slot, function: 0 }))
+        .filter(Device::exists)
+        .flat_map(|device| {
+            let functions = match device.read(HEADER_TYPE) & MULTI_FUNCTION {
+                0 => 1,
+                _ => 8,
+            };
+
+            (0..functions).map(move |function| Device { function, ..device })
+        })
+        .filter(Device::exists)
+}
+
+/// The first device with the given vendor and device IDs.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<Device> {
+    devices().find(|device| device.vendor_id() == vendor_id && device.device_id() == device_id)
+}
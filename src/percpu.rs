@@ -0,0 +1,56 @@
+use core::arch::asm;
+use core::sync::atomic::AtomicUsize;
+
+use alloc::boxed::Box;
+use x86_64::registers::model_specific::GsBase;
+use x86_64::VirtAddr;
+
+/// The maximum number of CPUs that the kernel will bring up
+pub const MAX_CPUS: usize = 16;
+
+/// Data that belongs to a single CPU, reachable through the GS base register.
+#[repr(C)]
+pub struct PerCpu {
+    /// Points back at this struct. Must stay the first field, since `current` reads it from
+    /// `gs:0` to turn the GS base into a normal pointer.
+    self_ptr: *const PerCpu,
+    /// The kernel's index for this CPU. The bootstrap processor is always 0.
+    pub cpu_id: usize,
+    pub apic_id: u8,
+    /// The virtual terminal of the task that this CPU is polling, or 0 between tasks
+    pub console: AtomicUsize,
+}
+
+/// Allocates this CPU's data and points the GS base at it. Must be called once on every CPU,
+/// before anything calls `current`.
+pub fn init(cpu_id: usize, apic_id: u8) {
+    let per_cpu = Box::leak(Box::new(PerCpu {
+        self_ptr: core::ptr::null(),
+        cpu_id,
+        apic_id,
+        console: AtomicUsize::new(0),
+    }));
+
+    per_cpu.self_ptr = per_cpu;
+
+    GsBase::write(VirtAddr::from_ptr(per_cpu));
+}
+
+/// Returns the data of the CPU that this is running on.
+pub fn current() -> &'static PerCpu {
+    let ptr: *const PerCpu;
+
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags));
+        &*ptr
+    }
+}
+
+/// Like `current`, but returns `None` if `init` hasn't been called on this CPU yet.
+pub fn try_current() -> Option<&'static PerCpu> {
+    if GsBase::read().is_null() {
+        return None;
+    }
+
+    Some(current())
+}
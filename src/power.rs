@@ -0,0 +1,117 @@
+use core::ptr;
+
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::acpi::{self, GenericAddress};
+use crate::memory;
+
+/// Turns the machine off. Tries ACPI S5 first, then the ports that emulators use as a stand-in.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+
+    if let Some(fadt) = acpi::fadt() {
+        if let Some((sleep_type_a, sleep_type_b)) = fadt.s5_sleep_types() {
+            acpi_sleep(&fadt, sleep_type_a, sleep_type_b);
+        }
+    }
+
+    // QEMU (newer and older versions), then VirtualBox
+    for (port, value) in [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)] {
+        unsafe { Port::<u16>::new(port).write(value) };
+    }
+
+    halt()
+}
+
+/// Restarts the machine. Tries the ACPI reset register, then the keyboard controller, and if
+/// all else fails, triple faults.
+pub fn reboot() -> ! {
+    interrupts::disable();
+
+    let fadt = acpi::fadt();
+
+    if let Some(fadt) = fadt {
+        match fadt.reset_register {
+            Some(GenericAddress::Io(port)) => unsafe { Port::<u8>::new(port).write(fadt.reset_value) },
+            Some(GenericAddress::Memory(addr)) => unsafe {
+                ptr::write_volatile(memory::phys_to_virt(addr).as_mut_ptr(), fadt.reset_value)
+            },
+            None => {}
+        }
+    }
+
+    // Pulse the CPU reset line through the keyboard controller
+    if fadt.is_none_or(|fadt| fadt.has_8042) {
+        let mut status: Port<u8> = Port::new(0x64);
+
+        unsafe {
+            while status.read() & 0b10 != 0 {
+                core::hint::spin_loop();
+            }
+
+            status.write(0xfe);
+        }
+    }
+
+    // With an empty IDT, the breakpoint can't be handled, which escalates to a triple fault
+    unsafe {
+        use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+        use x86_64::VirtAddr;
+
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+    }
+    interrupts::int3();
+
+    halt()
+}
+
+/// Enters an ACPI sleep state by writing SLP_TYP and SLP_EN to the PM1 control blocks.
+fn acpi_sleep(fadt: &acpi::Fadt, sleep_type_a: u8, sleep_type_b: u8) {
+    const SCI_EN: u16 = 1;
+    const SLP_EN: u16 = 1 << 13;
+
+    if fadt.pm1a_control_block == 0 {
+        return;
+    }
+
+    let mut pm1a_control: Port<u16> = Port::new(fadt.pm1a_control_block);
+
+    unsafe {
+        // Switch the chipset from legacy mode into ACPI mode if the firmware hasn't already
+        if pm1a_control.read() & SCI_EN == 0 && fadt.smi_command_port != 0 && fadt.acpi_enable != 0 {
+            Port::<u8>::new(fadt.smi_command_port).write(fadt.acpi_enable);
+
+            // Give up eventually; the emulator ports might still work
+            for _ in 0..1_000_000 {
+                if pm1a_control.read() & SCI_EN != 0 {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        pm1a_control.write(((sleep_type_a as u16) << 10) | SLP_EN);
+
+        if fadt.pm1b_control_block != 0 {
+            Port::<u16>::new(fadt.pm1b_control_block).write(((sleep_type_b as u16) << 10) | SLP_EN);
+        }
+    }
+}
+
+/// Exits QEMU through the `isa-debug-exit` device that `cargo test` adds (see Cargo.toml). QEMU
+/// then exits with `(code << 1) | 1`, so 33 for success.
+pub fn exit_qemu(success: bool) -> ! {
+    let code: u32 = if success { 0x10 } else { 0x11 };
+    unsafe { Port::<u32>::new(0xf4).write(code) };
+
+    halt()
+}
+
+fn halt() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,323 @@
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::acpi;
+use crate::config;
+use crate::time::Instant;
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, command when written
+const STATUS_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The waiting byte came from the mouse
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CONFIG_FIRST_PORT_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_PORT_IRQ: u8 = 1 << 1;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// Bytes that the keyboard sends back after a command
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+
+/// How many times a keyboard command byte is sent before giving up on it
+const RETRIES: usize = 3;
+/// How long to wait for the controller or the keyboard
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+/// The keyboard's repeat settings until `boot.cfg` or the `repeat` command changes them
+pub const DEFAULT_REPEAT_DELAY: Duration = Duration::from_millis(500);
+/// Characters per second
+pub const DEFAULT_REPEAT_RATE: u32 = 20;
+
+/// The size of the mouse's packets: 3 for a standard mouse, 4 for an IntelliMouse with a
+/// scroll wheel, or 0 if there's no mouse
+static MOUSE_PACKET_SIZE: AtomicU8 = AtomicU8::new(0);
+
+/// Set while a keyboard command is waiting for its response, so that the interrupt handler
+/// hands the response to `send_keyboard` instead of treating it as a scancode.
+static AWAITING_RESPONSE: AtomicBool = AtomicBool::new(false);
+/// The keyboard's response to the current command byte, or 0 if there isn't one yet
+static RESPONSE: AtomicU8 = AtomicU8::new(0);
+/// Only one keyboard command can be in flight at a time
+static COMMAND_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The firmware says there's no PS/2 controller
+    NoController,
+    Timeout,
+    /// The keyboard kept asking for a command byte to be sent again
+    TooManyResends,
+    SelfTestFailed(u8),
+    PortTestFailed(u8),
+}
+
+/// The keyboard's lock LEDs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+/// Sets up the controller, the keyboard and the mouse: tests the controller, enables both ports
+/// and their interrupts, then sets the keyboard's LEDs and its repeat rate from the boot
+/// configuration. Needs interrupts and `time` to be set up.
+pub fn init() -> Result<(), Ps2Error> {
+    if !acpi::fadt().is_none_or(|fadt| fadt.has_8042) {
+        return Err(Ps2Error::NoController);
+    }
+
+    // The controller's own replies come through the data port too, so keep the keyboard
+    // interrupt handler from taking them.
+    interrupts::without_interrupts(init_controller)?;
+
+    // A missing mouse isn't a reason to give up on the keyboard
+    if let Err(err) = interrupts::without_interrupts(init_mouse) {
+        log::info!("No PS/2 mouse ({err:?})");
+    }
+
+    let config = config::get();
+    set_typematic(config.repeat_delay, config.repeat_rate)?;
+    set_leds(Leds {
+        num_lock: true,
+        ..Leds::default()
+    })
+}
+
+fn init_controller() -> Result<(), Ps2Error> {
+    // Disable both ports, then throw away anything that was waiting
+    controller_command(0xad)?;
+    controller_command(0xa7)?;
+    flush();
+
+    controller_command(0x20)?;
+    let mut config = read_data()?;
+
+    config &= !(CONFIG_FIRST_PORT_IRQ | CONFIG_SECOND_PORT_IRQ);
+    // Keep translating to scancode set 1, which is what the keyboard driver decodes
+    config |= CONFIG_TRANSLATION;
+    write_config(config)?;
+
+    controller_command(0xaa)?;
+    match read_data()? {
+        0x55 => {}
+        result => return Err(Ps2Error::SelfTestFailed(result)),
+    }
+
+    // The self test can reset the controller
+    write_config(config)?;
+
+    controller_command(0xab)?;
+    match read_data()? {
+        0x00 => {}
+        result => return Err(Ps2Error::PortTestFailed(result)),
+    }
+
+    controller_command(0xae)?;
+    write_config(config | CONFIG_FIRST_PORT_IRQ)?;
+    flush();
+
+    Ok(())
+}
+
+/// Enables the second port, resets the mouse to its defaults, checks whether it has a scroll
+/// wheel, and turns on data reporting and the mouse interrupt.
+fn init_mouse() -> Result<(), Ps2Error> {
+    controller_command(0xa8)?;
+
+    controller_command(0xa9)?;
+    match read_data()? {
+        0x00 => {}
+        result => return Err(Ps2Error::PortTestFailed(result)),
+    }
+
+    mouse_command(0xf6)?;
+
+    // This sequence of sample rates is the IntelliMouse's signal to switch to 4-byte packets
+    for rate in [200, 100, 80] {
+        mouse_command(0xf3)?;
+        mouse_command(rate)?;
+    }
+
+    mouse_command(0xf2)?;
+    let packet_size = match read_data()? {
+        3 | 4 => 4,
+        _ => 3,
+    };
+
+    mouse_command(0xf4)?;
+
+    controller_command(0x20)?;
+    let config = read_data()?;
+    write_config(config | CONFIG_SECOND_PORT_IRQ)?;
+    flush();
+
+    MOUSE_PACKET_SIZE.store(packet_size, Ordering::Release);
+
+    Ok(())
+}
+
+/// The number of bytes in each mouse packet, or `None` if there's no mouse
+pub fn mouse_packet_size() -> Option<usize> {
+    match MOUSE_PACKET_SIZE.load(Ordering::Acquire) {
+        0 => None,
+        size => Some(size as usize),
+    }
+}
+
+/// Sends a byte to the mouse and waits for it to be acknowledged. Only for use with interrupts
+/// disabled, like `read_data`.
+fn mouse_command(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        controller_command(0xd4)?;
+        write_data(byte)?;
+
+        match read_data()? {
+            ACK => return Ok(()),
+            _ => continue,
+        }
+    }
+
+    Err(Ps2Error::TooManyResends)
+}
+
+/// Turns the keyboard's lock LEDs on and off.
+pub fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    let leds = (leds.scroll_lock as u8) | (leds.num_lock as u8) << 1 | (leds.caps_lock as u8) << 2;
+
+    send_keyboard(&[0xed, leds])
+}
+
+/// Sets how long a key has to be held before it repeats, and how many times per second it
+/// repeats. Both are rounded to the nearest setting the keyboard supports: a delay of 250 to
+/// 1000 ms, and a rate of 2 to 30 characters per second.
+pub fn set_typematic(delay: Duration, rate: u32) -> Result<(), Ps2Error> {
+    let delay = ((delay.as_millis() as u64 + 125) / 250).clamp(1, 4) as u8 - 1;
+
+    // The repeat period is (8 + low three bits) * 2^(next two bits) * 4.17 ms
+    let rate = (0..0x20u8)
+        .min_by_key(|setting| {
+            let period_us = (8 + (setting & 0b111) as u64) * (1 << (setting >> 3)) * 4170;
+            let millihertz = 1_000_000_000 / period_us;
+
+            millihertz.abs_diff(rate as u64 * 1000)
+        })
+        .unwrap();
+
+    send_keyboard(&[0xf3, delay << 5 | rate])
+}
+
+/// Called by the keyboard interrupt handler with every byte it reads. Returns `true` if the
+/// byte was the response to a command, in which case it isn't a scancode.
+pub fn take_response(byte: u8) -> bool {
+    if !AWAITING_RESPONSE.load(Ordering::Acquire) || !matches!(byte, ACK | RESEND) {
+        return false;
+    }
+
+    RESPONSE.store(byte, Ordering::Release);
+    true
+}
+
+/// Sends a command and its arguments to the keyboard, one byte at a time, waiting for each one
+/// to be acknowledged and resending it if the keyboard asks.
+fn send_keyboard(bytes: &[u8]) -> Result<(), Ps2Error> {
+    let _command = COMMAND_LOCK.lock();
+
+    AWAITING_RESPONSE.store(true, Ordering::Release);
+    let result = bytes.iter().try_for_each(|&byte| send_keyboard_byte(byte));
+    AWAITING_RESPONSE.store(false, Ordering::Release);
+
+    if let Err(err) = result {
+        log::warn!("PS/2 keyboard command {bytes:02x?} failed: {err:?}");
+    }
+
+    result
+}
+
+fn send_keyboard_byte(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        RESPONSE.store(0, Ordering::Release);
+        write_data(byte)?;
+
+        let start = Instant::now();
+
+        loop {
+            match RESPONSE.load(Ordering::Acquire) {
+                ACK => return Ok(()),
+                RESEND => break,
+                _ if start.elapsed() > TIMEOUT => return Err(Ps2Error::Timeout),
+                _ => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    Err(Ps2Error::TooManyResends)
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    controller_command(0x60)?;
+    write_data(config)
+}
+
+fn controller_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::<u8>::new(STATUS_PORT).write(command) };
+
+    Ok(())
+}
+
+fn write_data(data: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+
+    Ok(())
+}
+
+/// Reads a reply from the controller. Only for use with interrupts disabled, since the
+/// interrupt handler would take the byte otherwise.
+fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(|status| status & STATUS_OUTPUT_FULL != 0)?;
+
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn flush() {
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+/// Whether the controller has a byte from the keyboard waiting in the data port. The keyboard
+/// and mouse interrupts can be handled on different CPUs, so each only takes its own bytes.
+pub fn keyboard_byte_waiting() -> bool {
+    status() & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) == STATUS_OUTPUT_FULL
+}
+
+/// Whether the controller has a byte from the mouse waiting in the data port
+pub fn mouse_byte_waiting() -> bool {
+    status() & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) == STATUS_OUTPUT_FULL | STATUS_AUX_DATA
+}
+
+fn wait_for(condition: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    let start = Instant::now();
+
+    while !condition(status()) {
+        if start.elapsed() > TIMEOUT {
+            return Err(Ps2Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,709 @@
+pub mod sync;
+
+use core::task::{ready, Poll, Waker};
+use core::{future::Future, pin::Pin, task::Context};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+use spin::Mutex;
+
+use crate::percpu;
+
+pub struct Task {
+    id: TaskId,
+    name: Option<String>,
+    priority: Priority,
+    /// The virtual terminal that the task writes to and takes input focus on
+    console: usize,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+/// Which tasks the executor polls first. Higher classes go first, but lower ones still get a
+/// share of the CPU so that they can't starve.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Tasks that a user is waiting on, like input handling
+    Interactive,
+    #[default]
+    Normal,
+    /// Tasks that can wait until nothing else is running
+    Background,
+}
+
+impl Priority {
+    /// Every class, from highest to lowest
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Normal, Priority::Background];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl core::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            name: None,
+            priority: Priority::default(),
+            console: current_console(),
+            future: Box::pin(future),
+        }
+    }
+
+    /// Creates a task with a name to show in task listings.
+    pub fn named(name: impl Into<String>, future: impl Future<Output = ()> + Send + 'static) -> Task {
+        Task {
+            name: Some(name.into()),
+            ..Task::new(future)
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// The virtual terminal of the task that's running on this CPU. Outside of tasks, it's 0.
+/// Tasks start out on the terminal of the task that spawned them.
+pub fn current_console() -> usize {
+    percpu::try_current().map_or(0, |cpu| cpu.console.load(Ordering::Relaxed))
+}
+
+/// Spawns a task onto the current CPU's executor. Unlike `Executor::spawn`, this can be called
+/// from inside a running task.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Builder::new().spawn(future)
+}
+
+/// Like `spawn`, but gives the task a name to show in task listings.
+pub fn spawn_named<F>(name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Builder::new().name(name).spawn(future)
+}
+
+/// Sets up a task's name, priority and terminal before spawning it.
+#[derive(Default)]
+pub struct Builder {
+    name: Option<String>,
+    priority: Priority,
+    console: Option<usize>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Puts the task on a different virtual terminal from the task spawning it.
+    pub fn console(mut self, console: usize) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    /// Spawns the task onto the current CPU's executor.
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (mut task, handle) = joinable(future);
+        task.name = self.name;
+        task.priority = self.priority;
+        task.console = self.console.unwrap_or(task.console);
+
+        executor::spawn_task(task);
+
+        handle
+    }
+}
+
+/// Wraps a future in a task that stores its output for a `JoinHandle`.
+fn joinable<F>(future: F) -> (Task, JoinHandle<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let state = Arc::new(JoinState {
+        inner: Mutex::new(JoinInner {
+            result: None,
+            join_waker: None,
+            task_waker: None,
+        }),
+        aborted: AtomicBool::new(false),
+    });
+
+    let task = Task::new(Joinable {
+        future: Box::pin(future),
+        state: state.clone(),
+    });
+
+    (task, JoinHandle { state })
+}
+
+/// Why a task didn't produce a result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Aborted,
+}
+
+/// An owned permission to wait for a task's output, and a future that resolves to it. Dropping
+/// the handle detaches the task, which keeps running.
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+struct JoinState<T> {
+    inner: Mutex<JoinInner<T>>,
+    aborted: AtomicBool,
+}
+
+struct JoinInner<T> {
+    result: Option<Result<T, JoinError>>,
+    /// Woken when the task finishes
+    join_waker: Option<Waker>,
+    /// The task's own waker, so that `abort` can get it polled one last time
+    task_waker: Option<Waker>,
+}
+
+impl<T> JoinState<T> {
+    fn finish(&self, result: Result<T, JoinError>) {
+        let mut inner = self.inner.lock();
+
+        inner.result.get_or_insert(result);
+        inner.task_waker = None;
+        let join_waker = inner.join_waker.take();
+        drop(inner);
+
+        if let Some(waker) = join_waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task. It's dropped the next time the executor would poll it, and the handle
+    /// resolves to `JoinError::Aborted`. Does nothing if the task has already finished.
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::Release);
+
+        // A task that hasn't been polled yet is already queued
+        let task_waker = self.state.inner.lock().task_waker.clone();
+
+        if let Some(waker) = task_waker {
+            waker.wake();
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.inner.lock().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let mut inner = self.state.inner.lock();
+
+        match inner.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                inner.join_waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The future that actually runs as the task, forwarding its result to the `JoinHandle`.
+struct Joinable<F: Future> {
+    future: Pin<Box<F>>,
+    state: Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.state.aborted.load(Ordering::Acquire) {
+            self.state.finish(Err(JoinError::Aborted));
+            return Poll::Ready(());
+        }
+
+        self.state.inner.lock().task_waker.get_or_insert_with(|| context.waker().clone());
+
+        let output = ready!(self.future.as_mut().poll(context));
+        self.state.finish(Ok(output));
+
+        Poll::Ready(())
+    }
+}
+
+pub mod simple_executor {
+    use super::Task;
+    use alloc::collections::VecDeque;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    pub struct SimpleExecutor {
+        task_queue: VecDeque<Task>,
+    }
+
+    impl SimpleExecutor {
+        pub fn new() -> Self {
+            SimpleExecutor {
+                task_queue: VecDeque::new(),
+            }
+        }
+
+        pub fn spawn(&mut self, task: Task) {
+            self.task_queue.push_back(task)
+        }
+
+        pub fn run(&mut self) {
+            while let Some(mut task) = self.task_queue.pop_front() {
+                let waker = dummy_waker();
+                let mut context = Context::from_waker(&waker);
+                match task.poll(&mut context) {
+                    Poll::Ready(()) => {}
+                    Poll::Pending => self.task_queue.push_back(task),
+                }
+            }
+        }
+    }
+
+    fn dummy_raw_waker() -> RawWaker {
+        fn no_op(_: *const ()) {}
+        fn clone(_: *const ()) -> RawWaker {
+            dummy_raw_waker()
+        }
+
+        // TODO: Impl rest of operations
+        let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
+        RawWaker::new(0 as *const (), vtable)
+    }
+
+    fn dummy_waker() -> Waker {
+        unsafe { Waker::from_raw(dummy_raw_waker()) }
+    }
+}
+
+pub mod executor {
+    use super::{Builder, JoinHandle, Priority, Task, TaskId};
+    use crate::percpu;
+    use crate::time::{self, Instant};
+
+    use alloc::{collections::{BTreeMap, VecDeque}, string::String, sync::Arc, task::Wake, vec::Vec};
+    use conquer_once::spin::OnceCell;
+    use core::future::Future;
+    use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+    use core::task::{Waker, Context};
+    use core::time::Duration;
+    use crossbeam::queue::SegQueue;
+    use spin::Mutex;
+
+    /// How many tasks can be waiting on one CPU before the executor warns that it's falling behind
+    const BACKLOG_WARNING: usize = 256;
+    /// How many polls an executor makes before it starts a new round
+    const ROUND_POLLS: usize = 64;
+    /// How many times a task can be polled in one round. A task that keeps waking itself is held
+    /// back until the round ends, so that the rest of the ready tasks get a turn.
+    const TASK_BUDGET: u32 = 4;
+    /// Every this many picks, the executor takes a task from the lowest ready class instead of
+    /// the highest, so that background tasks still make progress under load.
+    const LOW_PRIORITY_INTERVAL: u64 = 8;
+
+    /// The tasks that are ready to be polled, one queue per CPU.
+    static READY_QUEUES: OnceCell<Vec<ReadyQueue>> = OnceCell::uninit();
+    /// Every task that hasn't finished yet, shared between the executors of all CPUs
+    static TASKS: Mutex<BTreeMap<TaskId, Arc<TaskEntry>>> = Mutex::new(BTreeMap::new());
+    /// The most recently finished tasks, oldest first, so that they still show up in listings
+    static FINISHED: Mutex<VecDeque<TaskInfo>> = Mutex::new(VecDeque::new());
+
+    /// How many finished tasks `FINISHED` remembers
+    const FINISHED_HISTORY: usize = 16;
+
+    struct TaskEntry {
+        /// Locked while the task is being polled, so that two CPUs never poll it at once
+        task: Mutex<Task>,
+        name: Option<String>,
+        priority: Priority,
+        stats: Mutex<TaskStats>,
+        task_waker: Arc<TaskWaker>,
+        waker: Waker,
+    }
+
+    /// Bookkeeping that the executor updates after every poll
+    #[derive(Default)]
+    struct TaskStats {
+        polls: u64,
+        busy_cycles: u64,
+        last_cpu: Option<usize>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TaskState {
+        /// Woken, and waiting in a ready queue
+        Ready,
+        /// Being polled right now
+        Running,
+        /// Waiting to be woken
+        Pending,
+        Finished,
+    }
+
+    /// A snapshot of a task's metadata and statistics
+    #[derive(Debug, Clone)]
+    pub struct TaskInfo {
+        pub id: TaskId,
+        pub name: Option<String>,
+        pub priority: Priority,
+        pub state: TaskState,
+        pub polls: u64,
+        /// The total time spent polling the task
+        pub busy: Duration,
+        pub last_woken: Option<Instant>,
+        /// The CPU that last polled the task
+        pub last_cpu: Option<usize>,
+    }
+
+    impl TaskEntry {
+        fn info(&self, task_id: TaskId) -> TaskInfo {
+            let state = if self.task.is_locked() {
+                TaskState::Running
+            } else if self.task_waker.queued.load(Ordering::Acquire) {
+                TaskState::Ready
+            } else {
+                TaskState::Pending
+            };
+
+            let last_woken = match self.task_waker.last_woken.load(Ordering::Relaxed) {
+                0 => None,
+                cycles => Some(Instant::from_cycles(cycles)),
+            };
+
+            let stats = self.stats.lock();
+
+            TaskInfo {
+                id: task_id,
+                name: self.name.clone(),
+                priority: self.priority,
+                state,
+                polls: stats.polls,
+                busy: time::cycles_to_duration(stats.busy_cycles),
+                last_woken,
+                last_cpu: stats.last_cpu,
+            }
+        }
+    }
+
+    /// Unbounded queues of ready tasks, one for each priority class. Each task is in at most one
+    /// queue at a time (see `TaskWaker::queued`), so they never hold more entries than there are
+    /// tasks.
+    struct ReadyQueue {
+        classes: [SegQueue<TaskId>; Priority::ALL.len()],
+        len: AtomicUsize,
+        /// Set once the backlog warning has been shown, until the queue drains again
+        warned: AtomicBool,
+    }
+
+    impl ReadyQueue {
+        fn new() -> Self {
+            ReadyQueue {
+                classes: Default::default(),
+                len: AtomicUsize::new(0),
+                warned: AtomicBool::new(false),
+            }
+        }
+
+        fn push(&self, cpu_id: usize, task_id: TaskId, priority: Priority) {
+            self.classes[priority.index()].push(task_id);
+
+            let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+
+            if len >= BACKLOG_WARNING && !self.warned.swap(true, Ordering::Relaxed) {
+                log::warn!("{len} tasks waiting on CPU {cpu_id}; the executor is falling behind");
+            }
+        }
+
+        fn pop(&self, priority: Priority) -> Option<TaskId> {
+            let task_id = self.classes[priority.index()].pop()?;
+
+            if self.len.fetch_sub(1, Ordering::Relaxed) <= BACKLOG_WARNING / 2 {
+                self.warned.store(false, Ordering::Relaxed);
+            }
+
+            Some(task_id)
+        }
+
+        fn is_empty(&self) -> bool {
+            self.classes.iter().all(SegQueue::is_empty)
+        }
+    }
+
+    /// Creates the ready queues. Must be called once, before any executor is created.
+    pub fn init(cpu_count: usize) {
+        READY_QUEUES.init_once(|| (0..cpu_count).map(|_| ReadyQueue::new()).collect());
+    }
+
+    fn ready_queues() -> &'static [ReadyQueue] {
+        READY_QUEUES
+            .try_get()
+            .expect("Executor used before executor::init was called")
+    }
+
+    /// Adds a task to the shared task list and queues it on the current CPU.
+    pub(super) fn spawn_task(mut task: Task) {
+        let task_id = task.id;
+        let task_waker = Arc::new(TaskWaker {
+            task_id,
+            priority: task.priority,
+            queued: AtomicBool::new(false),
+            last_woken: AtomicU64::new(0),
+        });
+        let entry = Arc::new(TaskEntry {
+            name: task.name.take(),
+            priority: task.priority,
+            task: Mutex::new(task),
+            stats: Mutex::new(TaskStats::default()),
+            waker: Waker::from(task_waker.clone()),
+            task_waker,
+        });
+
+        if TASKS.lock().insert(task_id, entry.clone()).is_some() {
+            unreachable!("Task with same ID already in tasks");
+        }
+
+        entry.task_waker.wake_task();
+    }
+
+    /// Runs tasks on the current CPU. Every CPU runs its own executor; they share their tasks,
+    /// and an executor with nothing to do steals ready tasks from the other CPUs.
+    pub struct Executor {
+        cpu_id: usize,
+        /// How many tasks this executor has picked, for `LOW_PRIORITY_INTERVAL`
+        picks: u64,
+    }
+
+    impl Executor {
+        pub fn new() -> Self {
+            Executor {
+                cpu_id: percpu::current().cpu_id,
+                picks: 0,
+            }
+        }
+
+        pub fn spawn<F>(&mut self, future: F) -> JoinHandle<F::Output>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send + 'static,
+        {
+            Builder::new().spawn(future)
+        }
+
+        /// Like `spawn`, but gives the task a name to show in task listings.
+        pub fn spawn_named<F>(&mut self, name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send + 'static,
+        {
+            Builder::new().name(name).spawn(future)
+        }
+
+        /// A snapshot of every live task on every CPU, plus the last few that finished, ordered
+        /// by ID.
+        pub fn tasks() -> Vec<TaskInfo> {
+            let entries: Vec<_> = TASKS
+                .lock()
+                .iter()
+                .map(|(&task_id, entry)| (task_id, entry.clone()))
+                .collect();
+
+            let mut tasks: Vec<_> = entries
+                .iter()
+                .map(|(task_id, entry)| entry.info(*task_id))
+                .chain(FINISHED.lock().iter().cloned())
+                .collect();
+
+            tasks.sort_by_key(|task| task.id);
+            tasks
+        }
+
+        pub fn run(&mut self) -> ! {
+            loop {
+                self.run_ready_tasks();
+                self.sleep_if_idle();
+            }
+        }
+
+        fn sleep_if_idle(&self) {
+            use x86_64::instructions::interrupts;
+
+            // Interrupts are disabled while checking, so that a wakeup from an interrupt
+            // handler can't slip in between the check and the `hlt`.
+            interrupts::disable();
+
+            if ready_queues().iter().all(ReadyQueue::is_empty) {
+                interrupts::enable_and_hlt();
+            } else {
+                interrupts::enable();
+            }
+        }
+
+        /// Takes the highest priority ready task, from this CPU's queue if it can, otherwise
+        /// by stealing from another CPU.
+        fn next_task(&mut self) -> Option<TaskId> {
+            let queues = ready_queues();
+
+            self.picks += 1;
+
+            let mut classes = Priority::ALL;
+            if self.picks.is_multiple_of(LOW_PRIORITY_INTERVAL) {
+                classes.reverse();
+            }
+
+            classes.into_iter().find_map(|priority| {
+                (0..queues.len())
+                    .map(|offset| &queues[(self.cpu_id + offset) % queues.len()])
+                    .find_map(|queue| queue.pop(priority))
+            })
+        }
+
+        /// Polls ready tasks for one round of at most `ROUND_POLLS` polls.
+        fn run_ready_tasks(&mut self) {
+            let mut round_polls: BTreeMap<TaskId, u32> = BTreeMap::new();
+            let mut held_back = Vec::new();
+
+            for _ in 0..ROUND_POLLS {
+                let Some(task_id) = self.next_task() else {
+                    break;
+                };
+
+                let entry = match TASKS.lock().get(&task_id) {
+                    Some(entry) => entry.clone(),
+                    None => continue
+                };
+
+                let polls = round_polls.entry(task_id).or_insert(0);
+                if *polls >= TASK_BUDGET {
+                    // Still queued as far as its waker is concerned; it goes back in below
+                    held_back.push(entry);
+                    continue;
+                }
+                *polls += 1;
+
+                // If another CPU is polling the task right now, it was woken during that poll;
+                // put it back so that the wakeup isn't lost.
+                let Some(mut task) = entry.task.try_lock() else {
+                    entry.task_waker.requeue(self.cpu_id);
+                    continue;
+                };
+
+                // Cleared before polling, so that a wakeup during the poll queues the task again
+                entry.task_waker.queued.store(false, Ordering::Release);
+
+                let mut context = Context::from_waker(&entry.waker);
+                let cpu = percpu::current();
+
+                cpu.console.store(task.console, Ordering::Relaxed);
+                let start = Instant::now();
+                let poll = task.poll(&mut context);
+                let busy_cycles = Instant::now().cycles() - start.cycles();
+                cpu.console.store(0, Ordering::Relaxed);
+
+                let mut stats = entry.stats.lock();
+                stats.polls += 1;
+                stats.busy_cycles += busy_cycles;
+                stats.last_cpu = Some(self.cpu_id);
+                drop(stats);
+
+                if poll.is_ready() {
+                    TASKS.lock().remove(&task_id);
+
+                    let mut info = entry.info(task_id);
+                    info.state = TaskState::Finished;
+
+                    let mut finished = FINISHED.lock();
+                    if finished.len() == FINISHED_HISTORY {
+                        finished.pop_front();
+                    }
+                    finished.push_back(info);
+                }
+            }
+
+            for entry in held_back {
+                entry.task_waker.requeue(self.cpu_id);
+            }
+        }
+    }
+
+    struct TaskWaker {
+        task_id: TaskId,
+        priority: Priority,
+        /// Whether the task is in a ready queue, so that repeated wakeups only queue it once
+        queued: AtomicBool,
+        /// When the task was last woken, in timestamp counter cycles, or 0 if it never was
+        last_woken: AtomicU64,
+    }
+
+    impl TaskWaker {
+        /// Queues the task on the CPU that woke it, which is awake by definition.
+        fn wake_task(&self) {
+            self.last_woken.store(Instant::now().cycles(), Ordering::Relaxed);
+
+            if self.queued.swap(true, Ordering::AcqRel) {
+                return;
+            }
+
+            self.requeue(percpu::current().cpu_id);
+        }
+
+        /// Puts a task that is already marked as queued back into a ready queue.
+        fn requeue(&self, cpu_id: usize) {
+            ready_queues()[cpu_id].push(cpu_id, self.task_id, self.priority)
+        }
+    }
+
+    impl Wake for TaskWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_task();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.wake_task();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Synchronization primitives for tasks. Instead of spinning, a task that has to wait registers
+//! its waker and yields back to the executor until it's woken.
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+pub mod mpsc;
+pub mod oneshot;
+
+// Not every primitive has a user in the kernel yet
+#[allow(unused_imports)]
+pub use mutex::{Mutex, MutexGuard};
+#[allow(unused_imports)]
+pub use notify::{Notified, Notify};
+#[allow(unused_imports)]
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[allow(unused_imports)]
+pub use semaphore::{Acquire, AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+use x86_64::instructions::interrupts;
+
+/// Locks a spinlock with interrupts disabled, so that interrupt handlers can notify tasks and
+/// send on channels without deadlocking against the task they interrupted.
+fn with_lock<T, R>(lock: &spin::Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut lock.lock()))
+}
+
+/// The tasks waiting on a primitive. Each waiter is identified by a key that it keeps while it
+/// waits; waking a waiter removes its key, which is how a waiter finds out it was woken.
+struct WaitQueue {
+    waiters: spin::Mutex<WaitList>,
+}
+
+struct WaitList {
+    next_key: u64,
+    waiters: VecDeque<(u64, Waker)>,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        WaitQueue {
+            waiters: spin::Mutex::new(WaitList {
+                next_key: 0,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Adds a waiter to the back of the queue, or updates its waker if it's already queued.
+    fn register(&self, key: &mut Option<u64>, waker: &Waker) {
+        with_lock(&self.waiters, |list| {
+            if let Some(queued) = key.and_then(|key| list.waiters.iter_mut().find(|(k, _)| *k == key)) {
+                if !queued.1.will_wake(waker) {
+                    queued.1 = waker.clone();
+                }
+                return;
+            }
+
+            let new_key = list.next_key;
+            list.next_key += 1;
+            list.waiters.push_back((new_key, waker.clone()));
+            *key = Some(new_key);
+        })
+    }
+
+    /// Takes a waiter out of the queue. Returns `false` if it had already been woken (or was
+    /// never queued).
+    fn remove(&self, key: &mut Option<u64>) -> bool {
+        let Some(key) = key.take() else {
+            return false;
+        };
+
+        with_lock(&self.waiters, |list| {
+            match list.waiters.iter().position(|(k, _)| *k == key) {
+                Some(index) => {
+                    list.waiters.remove(index);
+                    true
+                }
+                None => false
+            }
+        })
+    }
+
+    /// Queues a waiter, unless it has been woken since it was queued or `ready` says it
+    /// doesn't have to wait. Returns whether it's done waiting. `ready` runs with the queue
+    /// locked, so nothing can be woken between it and queueing.
+    fn register_unless(&self, key: &mut Option<u64>, waker: &Waker, ready: impl FnOnce() -> bool) -> bool {
+        with_lock(&self.waiters, |list| {
+            if let Some(queued_key) = *key {
+                match list.waiters.iter_mut().find(|(k, _)| *k == queued_key) {
+                    Some(queued) => {
+                        if !queued.1.will_wake(waker) {
+                            queued.1 = waker.clone();
+                        }
+                        return false;
+                    }
+                    None => {
+                        *key = None;
+                        return true;
+                    }
+                }
+            }
+
+            if ready() {
+                return true;
+            }
+
+            let new_key = list.next_key;
+            list.next_key += 1;
+            list.waiters.push_back((new_key, waker.clone()));
+            *key = Some(new_key);
+            false
+        })
+    }
+
+    /// Wakes the waiter at the front of the queue. Returns `false` if nobody was waiting.
+    fn wake_one(&self) -> bool {
+        self.wake_one_or(|| {})
+    }
+
+    /// Wakes the waiter at the front of the queue, or runs `otherwise` with the queue still
+    /// locked if nobody was waiting.
+    fn wake_one_or(&self, otherwise: impl FnOnce()) -> bool {
+        let waiter = with_lock(&self.waiters, |list| {
+            let waiter = list.waiters.pop_front();
+            if waiter.is_none() {
+                otherwise();
+            }
+            waiter
+        });
+
+        // Wakers are woken outside the lock, since waking can run arbitrary code
+        match waiter {
+            Some((_, waker)) => {
+                waker.wake();
+                true
+            }
+            None => false
+        }
+    }
+
+    fn wake_all(&self) {
+        let waiters = with_lock(&self.waiters, |list| core::mem::take(&mut list.waiters));
+
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! A bounded multi-producer, single-consumer channel.
+
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use alloc::{collections::VecDeque, sync::Arc};
+use futures_util::stream::Stream;
+
+use super::{with_lock, Semaphore, WaitQueue};
+
+/// Creates a channel that holds up to `capacity` messages. Senders wait while it's full.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan {
+        queue: spin::Mutex::new(VecDeque::with_capacity(capacity)),
+        slots: Semaphore::new(capacity),
+        senders: AtomicUsize::new(1),
+        receiver: WaitQueue::new(),
+    });
+
+    (
+        Sender { chan: chan.clone() },
+        Receiver { chan, waiter: None },
+    )
+}
+
+struct Chan<T> {
+    queue: spin::Mutex<VecDeque<T>>,
+    /// One permit for every free slot in the queue. Closed when the receiver goes away.
+    slots: Semaphore,
+    senders: AtomicUsize,
+    receiver: WaitQueue,
+}
+
+impl<T> Chan<T> {
+    fn push(&self, value: T) {
+        with_lock(&self.queue, |queue| queue.push_back(value));
+        self.receiver.wake_one();
+    }
+
+    fn pop(&self) -> Option<T> {
+        let value = with_lock(&self.queue, VecDeque::pop_front)?;
+        self.slots.add_permits(1);
+
+        Some(value)
+    }
+}
+
+/// The receiver is gone, so the value couldn't be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and the channel is empty
+    Disconnected,
+}
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting for a free slot if the channel is full.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.chan.slots.acquire().await {
+            Ok(permit) => permit.forget(),
+            Err(_) => return Err(SendError(value)),
+        }
+
+        self.chan.push(value);
+        Ok(())
+    }
+
+    /// Sends a value if there's room. Safe to call from interrupt handlers.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.chan.slots.try_acquire() {
+            Ok(permit) => permit.forget(),
+            Err(super::TryAcquireError::NoPermits) => return Err(TrySendError::Full(value)),
+            Err(super::TryAcquireError::Closed) => return Err(TrySendError::Closed(value)),
+        }
+
+        self.chan.push(value);
+        Ok(())
+    }
+
+    /// Whether the receiver is gone
+    pub fn is_closed(&self) -> bool {
+        self.chan.slots.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Ordering::Relaxed);
+
+        Sender { chan: self.chan.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.chan.receiver.wake_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+    waiter: Option<u64>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Returns `None` once every sender is gone and the channel is
+    /// empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|context| self.poll_recv(context)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self.chan.pop() {
+            Some(value) => Ok(value),
+            None if self.chan.senders.load(Ordering::Acquire) == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Stops any more values from being sent. Values already in the channel can still be
+    /// received.
+    pub fn close(&mut self) {
+        self.chan.slots.close();
+    }
+
+    pub fn poll_recv(&mut self, context: &mut Context) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(value) => return self.ready(Some(value)),
+            Err(TryRecvError::Disconnected) => return self.ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        self.chan.receiver.register(&mut self.waiter, context.waker());
+
+        // A sender might have pushed or gone away before registering
+        match self.try_recv() {
+            Ok(value) => self.ready(Some(value)),
+            Err(TryRecvError::Disconnected) => self.ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    fn ready(&mut self, value: Option<T>) -> Poll<Option<T>> {
+        self.chan.receiver.remove(&mut self.waiter);
+        Poll::Ready(value)
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(context)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.slots.close();
+        self.chan.receiver.remove(&mut self.waiter);
+    }
+}
@@ -0,0 +1,79 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use super::WaitQueue;
+
+/// Wakes waiting tasks without passing any data. Safe to notify from interrupt handlers.
+pub struct Notify {
+    /// Set by a `notify_one` that found nobody waiting, for the next `notified` to pick up.
+    /// Only changed with `waiters` locked, so it's never set while anyone is queued.
+    permit: AtomicBool,
+    waiters: WaitQueue,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            permit: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Waits for a notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+
+    /// Wakes the task that has waited the longest. If no task is waiting, the next call to
+    /// `notified` completes straight away.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one_or(|| self.permit.store(true, Ordering::Release));
+    }
+
+    /// Wakes every task that is waiting right now. Unlike `notify_one`, this isn't remembered
+    /// for later waiters.
+    pub fn notify_waiters(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+/// The future returned by `Notify::notified`
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let notify = this.notify;
+
+        // Taking the permit and queueing happen under one lock, so a `notify_one` either sees
+        // this waiter or leaves a permit that's taken here
+        let ready = notify.waiters.register_unless(&mut this.waiter, context.waker(), || {
+            notify.permit.swap(false, Ordering::AcqRel)
+        });
+
+        if ready {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        // A waiter that was woken but never completed would swallow the notification
+        if self.waiter.is_some() && !self.notify.waiters.remove(&mut self.waiter) {
+            self.notify.notify_one();
+        }
+    }
+}
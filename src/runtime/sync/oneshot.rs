@@ -0,0 +1,113 @@
+//! A channel for sending a single value.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use alloc::sync::Arc;
+use futures_util::task::AtomicWaker;
+
+use super::with_lock;
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        value: spin::Mutex::new(None),
+        complete: AtomicBool::new(false),
+        receiver_closed: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+struct Inner<T> {
+    value: spin::Mutex<Option<T>>,
+    /// Set once the sender has sent its value or gone away
+    complete: AtomicBool,
+    receiver_closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl<T> Inner<T> {
+    fn complete(&self) {
+        self.complete.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+/// The sender went away without sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends the value, or hands it back if the receiver is gone. Safe to call from interrupt
+    /// handlers.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.is_closed() {
+            return Err(value);
+        }
+
+        with_lock(&self.inner.value, |slot| *slot = Some(value));
+        // Dropping `self` marks the channel complete
+
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.receiver_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.complete();
+    }
+}
+
+/// A future that resolves to the sent value.
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if !self.inner.complete.load(Ordering::Acquire) {
+            return Err(TryRecvError::Empty);
+        }
+
+        with_lock(&self.inner.value, Option::take).ok_or(TryRecvError::Closed)
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        this.inner.waker.register(context.waker());
+
+        match this.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_closed.store(true, Ordering::Release);
+    }
+}
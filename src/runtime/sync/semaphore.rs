@@ -0,0 +1,251 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use super::with_lock;
+
+/// A counting semaphore. Tasks that can't get enough permits wait without spinning, and are
+/// served in the order they started waiting: a task that wants many permits holds back the
+/// ones behind it rather than being overtaken forever.
+pub struct Semaphore {
+    state: spin::Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    next_key: u64,
+    /// Oldest first
+    waiters: VecDeque<Waiter>,
+    /// Waiters that have been handed their permits, but haven't been polled since
+    granted: Vec<u64>,
+}
+
+struct Waiter {
+    key: u64,
+    permits: usize,
+    waker: Waker,
+}
+
+/// The semaphore was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcquireError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: spin::Mutex::new(State {
+                permits,
+                closed: false,
+                next_key: 0,
+                waiters: VecDeque::new(),
+                granted: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        with_lock(&self.state, |state| state.permits)
+    }
+
+    /// Waits for a single permit.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits until `permits` permits are available, and takes them all at once.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes permits straight away. Fails while anyone is waiting, even if there would be
+    /// enough, so that it can't overtake them.
+    pub fn try_acquire_many(&self, permits: usize) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        with_lock(&self.state, |state| {
+            if state.closed {
+                Err(TryAcquireError::Closed)
+            } else if state.take_permits(permits) {
+                Ok(SemaphorePermit {
+                    semaphore: self,
+                    permits,
+                })
+            } else {
+                Err(TryAcquireError::NoPermits)
+            }
+        })
+    }
+
+    /// Returns permits to the semaphore, and hands them on to the waiters at the front of the
+    /// queue for as long as there are enough. Safe to call from interrupt handlers.
+    pub fn add_permits(&self, permits: usize) {
+        with_lock(&self.state, |state| state.permits += permits);
+
+        // Wakers are woken outside the lock, since waking can run arbitrary code
+        while let Some(waker) = with_lock(&self.state, State::grant_next) {
+            waker.wake();
+        }
+    }
+
+    /// Makes every current and future `acquire` fail. Permits that are already held stay valid.
+    pub fn close(&self) {
+        let waiters = with_lock(&self.state, |state| {
+            state.closed = true;
+            core::mem::take(&mut state.waiters)
+        });
+
+        for waiter in waiters {
+            waiter.waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        with_lock(&self.state, |state| state.closed)
+    }
+}
+
+impl State {
+    /// Takes permits if nobody is waiting for them first
+    fn take_permits(&mut self, permits: usize) -> bool {
+        if !self.waiters.is_empty() || self.permits < permits {
+            return false;
+        }
+
+        self.permits -= permits;
+        true
+    }
+
+    /// Hands permits to the waiter at the front of the queue, if there are enough for it
+    fn grant_next(&mut self) -> Option<Waker> {
+        if self.waiters.front()?.permits > self.permits {
+            return None;
+        }
+
+        let waiter = self.waiters.pop_front()?;
+        self.permits -= waiter.permits;
+        self.granted.push(waiter.key);
+
+        Some(waiter.waker)
+    }
+}
+
+/// Permits taken from a semaphore. They're given back when this is dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits out of the semaphore for good.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+/// The future returned by `Semaphore::acquire`
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    /// The key of this waiter in the queue, once it's waiting
+    waiter: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<SemaphorePermit<'a>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let ready = with_lock(&this.semaphore.state, |state| {
+            if let Some(key) = this.waiter {
+                if let Some(index) = state.granted.iter().position(|&granted| granted == key) {
+                    state.granted.swap_remove(index);
+                    this.waiter = None;
+                    return Some(Ok(()));
+                }
+
+                if state.closed {
+                    this.waiter = None;
+                    return Some(Err(AcquireError));
+                }
+
+                if let Some(waiter) = state.waiters.iter_mut().find(|waiter| waiter.key == key) {
+                    if !waiter.waker.will_wake(context.waker()) {
+                        waiter.waker = context.waker().clone();
+                    }
+                }
+                return None;
+            }
+
+            if state.closed {
+                return Some(Err(AcquireError));
+            }
+
+            if state.take_permits(this.permits) {
+                return Some(Ok(()));
+            }
+
+            let key = state.next_key;
+            state.next_key += 1;
+            state.waiters.push_back(Waiter {
+                key,
+                permits: this.permits,
+                waker: context.waker().clone(),
+            });
+            this.waiter = Some(key);
+
+            None
+        });
+
+        match ready {
+            Some(result) => Poll::Ready(result.map(|()| SemaphorePermit {
+                semaphore: this.semaphore,
+                permits: this.permits,
+            })),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.waiter.take() else {
+            return;
+        };
+
+        // Permits that were granted but never picked up go back. A waiter leaving the front of
+        // the queue might also let the ones behind it through.
+        let returned = with_lock(&self.semaphore.state, |state| {
+            if let Some(index) = state.granted.iter().position(|&granted| granted == key) {
+                state.granted.swap_remove(index);
+                return self.permits;
+            }
+
+            state.waiters.retain(|waiter| waiter.key != key);
+            0
+        });
+
+        self.semaphore.add_permits(returned);
+    }
+}
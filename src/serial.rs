@@ -0,0 +1,30 @@
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+use uart_16550::SerialPort;
+
+lazy_static! {
+    /// The first serial port (COM1). QEMU forwards it to stdio with `-serial stdio`.
+    static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+/// Acquires the first serial port.
+pub fn lock<'a>() -> MutexGuard<'a, SerialPort> {
+    SERIAL1.lock()
+}
+
+/// Attempts to lock the first serial port, to evade deadlocks from interrupt handlers.
+pub fn try_lock<'a>() -> Option<MutexGuard<'a, SerialPort>> {
+    SERIAL1.try_lock()
+}
+
+/// Forcefully unlocks the serial port and then locks it again.
+/// This is unsafe because it might unlock the Mutex while it's still in use.
+pub unsafe fn force_lock<'a>() -> MutexGuard<'a, SerialPort> {
+    // SAFETY: Might unlock the port while it's being used, interleaving output
+    SERIAL1.force_unlock();
+    SERIAL1.lock()
+}
@@ -0,0 +1,316 @@
+use core::arch::global_asm;
+use core::ptr::{self, addr_of};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use alloc::format;
+use conquer_once::spin::OnceCell;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+use crate::gdt::CpuTables;
+use crate::interrupts::{self, apic};
+use crate::runtime::executor::{self, Executor};
+use crate::{acpi, memory, percpu, stack};
+
+/// The size of each application processor's kernel stack, in pages
+const AP_STACK_PAGES: u64 = 32;
+
+/// The number of CPUs that are up and running
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// Set by an application processor once it no longer needs the trampoline
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+/// The page below 1 MiB that application processors start executing in
+static TRAMPOLINE_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+
+// Application processors start in real mode at the start of a page below 1 MiB, with CS set to
+// the page's segment. This code is copied to that page, and goes straight to long mode using
+// the kernel's page table, then calls into `ap_main` on the stack that the BSP allocated for it.
+//
+// The fields after the code are filled in by the BSP before each startup. The page has to be
+// identity mapped, since the CPU keeps executing at the same linear address once paging is on.
+global_asm!(
+    r#"
+.pushsection .rodata.ap_trampoline, "a"
+.global ap_trampoline_start
+.global ap_trampoline_end
+.global ap_long_mode
+.global ap_gdt
+.global ap_gdt_pointer
+.global ap_long_mode_pointer
+.global ap_cr3
+.global ap_stack_top
+.global ap_boot_info
+.global ap_entry
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    mov ax, cs
+    mov ds, ax
+
+    lgdt [ap_gdt_pointer - ap_trampoline_start]
+
+    // CR4.PAE
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    mov eax, [ap_cr3 - ap_trampoline_start]
+    mov cr3, eax
+
+    // EFER.LME and EFER.NXE (the kernel maps pages as no-execute)
+    mov ecx, 0xc0000080
+    rdmsr
+    or eax, (1 << 8) | (1 << 11)
+    wrmsr
+
+    // CR0.PG, CR0.WP and CR0.PE all at once
+    mov eax, cr0
+    or eax, (1 << 31) | (1 << 16) | 1
+    mov cr0, eax
+
+    // jmp far dword [ap_long_mode_pointer] (an m16:32 pointer, hence the operand size prefix)
+    .byte 0x66, 0xff, 0x2e
+    .2byte ap_long_mode_pointer - ap_trampoline_start
+
+.code64
+ap_long_mode:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    mov rsp, [rip + ap_stack_top]
+    mov rdi, [rip + ap_boot_info]
+    mov rax, [rip + ap_entry]
+    call rax
+    ud2
+
+.balign 8
+ap_gdt:
+    .8byte 0
+    // 64-bit code segment
+    .8byte 0x00af9a000000ffff
+    // Data segment
+    .8byte 0x00cf92000000ffff
+ap_gdt_end:
+
+ap_gdt_pointer:
+    .2byte ap_gdt_end - ap_gdt - 1
+    // Patched to the linear address of ap_gdt
+    .4byte 0
+.balign 8
+ap_long_mode_pointer:
+    // Patched to the linear address of ap_long_mode
+    .4byte 0
+    .2byte 0x08
+.balign 8
+ap_cr3:
+    .8byte 0
+ap_stack_top:
+    .8byte 0
+ap_boot_info:
+    .8byte 0
+ap_entry:
+    .8byte 0
+ap_trampoline_end:
+.popsection
+"#
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_long_mode: u8;
+    static ap_gdt: u8;
+    static ap_gdt_pointer: u8;
+    static ap_long_mode_pointer: u8;
+    static ap_cr3: u8;
+    static ap_stack_top: u8;
+    static ap_boot_info: u8;
+    static ap_entry: u8;
+}
+
+/// Everything an application processor needs to set itself up
+struct ApBootInfo {
+    cpu_id: usize,
+    apic_id: u8,
+    tables: &'static CpuTables,
+}
+
+/// Reserves the page that application processors start in. The SIPI vector can only address
+/// pages below 1 MiB, so this should be called before any other frame is allocated, while the
+/// frame allocator is still handing out low memory.
+pub fn reserve_trampoline(frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return;
+    };
+
+    if frame.start_address().as_u64() < 0x10_0000 {
+        TRAMPOLINE_FRAME.init_once(|| frame);
+    }
+}
+
+/// The number of CPUs that are up and running
+pub fn cpus_online() -> usize {
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+/// Sets up per-CPU data and executor queues, then starts every application processor listed in
+/// the MADT. Needs the APIC, the timer and interrupts to be enabled.
+pub fn init(
+    mapper: &mut (impl Mapper<Size4KiB> + Translate),
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let madt = acpi::madt().filter(|_| interrupts::apic_enabled());
+    let local_apic = madt.as_ref().and(apic::local_apic());
+
+    let bsp_apic_id = local_apic.map_or(0, |local_apic| local_apic.id());
+
+    let application_processors = madt
+        .iter()
+        .flat_map(|madt| madt.processors.iter())
+        .filter(|processor| processor.usable && processor.apic_id != bsp_apic_id)
+        .take(percpu::MAX_CPUS - 1);
+
+    let application_processors: alloc::vec::Vec<_> = application_processors.collect();
+
+    executor::init(application_processors.len() + 1);
+
+    let (Some(local_apic), Ok(trampoline)) = (local_apic, TRAMPOLINE_FRAME.try_get()) else {
+        return Ok(());
+    };
+
+    if application_processors.is_empty() {
+        return Ok(());
+    }
+
+    install_trampoline(*trampoline, mapper, frame_allocator)?;
+
+    for (index, processor) in application_processors.iter().enumerate() {
+        let cpu_id = index + 1;
+
+        let stack = stack::alloc_stack(
+            format!("CPU {cpu_id} kernel stack").leak(),
+            AP_STACK_PAGES,
+            mapper,
+            frame_allocator,
+        )?;
+
+        let boot_info = Box::leak(Box::new(ApBootInfo {
+            cpu_id,
+            apic_id: processor.apic_id,
+            tables: CpuTables::new(cpu_id, mapper, frame_allocator)?,
+        }));
+
+        unsafe {
+            write_field(*trampoline, addr_of!(ap_stack_top), stack.top.as_u64());
+            write_field(*trampoline, addr_of!(ap_boot_info), boot_info as *const ApBootInfo as u64);
+        }
+
+        AP_STARTED.store(false, Ordering::SeqCst);
+
+        // INIT, then wait 10ms. Then SIPI, which starts the CPU at the trampoline. Send a second
+        // SIPI if the first one went missing.
+        let vector = (trampoline.start_address().as_u64() >> 12) as u32;
+
+        local_apic.send_ipi(processor.apic_id, 0x4500);
+        interrupts::wait_ticks(1);
+
+        for _ in 0..2 {
+            local_apic.send_ipi(processor.apic_id, 0x4600 | vector);
+            interrupts::wait_ticks(1);
+
+            if AP_STARTED.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+
+        // Wait up to a second for the CPU to leave the trampoline, since the next CPU reuses it
+        for _ in 0..interrupts::TIMER_HZ {
+            if AP_STARTED.load(Ordering::SeqCst) {
+                break;
+            }
+            interrupts::wait_ticks(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the trampoline into its page, identity maps it, and fills in the fields that are the
+/// same for every application processor.
+fn install_trampoline(
+    frame: PhysFrame,
+    mapper: &mut (impl Mapper<Size4KiB> + Translate),
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let phys = frame.start_address();
+
+    // The bootloader might have identity mapped low memory already
+    if mapper.translate_addr(VirtAddr::new(phys.as_u64())) != Some(phys) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.identity_map(frame, flags, frame_allocator)?.flush() };
+    }
+
+    let (cr3_frame, _) = Cr3::read();
+    assert!(
+        cr3_frame.start_address().as_u64() <= u32::MAX as u64,
+        "The trampoline can only load a page table below 4 GiB"
+    );
+
+    unsafe {
+        let start = addr_of!(ap_trampoline_start);
+        let len = addr_of!(ap_trampoline_end).offset_from(start) as usize;
+
+        ptr::copy_nonoverlapping(start, memory::phys_to_virt(phys).as_mut_ptr(), len);
+
+        // `lgdt` and far jumps take linear addresses, which depend on where the page is
+        let gdt = phys.as_u64() + offset_of(addr_of!(ap_gdt)) as u64;
+        let long_mode = phys.as_u64() + offset_of(addr_of!(ap_long_mode)) as u64;
+
+        write_u32(frame, offset_of(addr_of!(ap_gdt_pointer)) + 2, gdt as u32);
+        write_u32(frame, offset_of(addr_of!(ap_long_mode_pointer)), long_mode as u32);
+        write_field(frame, addr_of!(ap_cr3), cr3_frame.start_address().as_u64());
+        let entry: extern "C" fn(&'static ApBootInfo) -> ! = ap_main;
+        write_field(frame, addr_of!(ap_entry), entry as usize as u64);
+    }
+
+    Ok(())
+}
+
+/// The offset of a trampoline symbol from the start of the trampoline
+unsafe fn offset_of(symbol: *const u8) -> usize {
+    symbol.offset_from(addr_of!(ap_trampoline_start)) as usize
+}
+
+unsafe fn write_u32(frame: PhysFrame, offset: usize, value: u32) {
+    let addr = memory::phys_to_virt(frame.start_address() + offset as u64);
+    ptr::write_volatile(addr.as_mut_ptr(), value);
+}
+
+/// Writes one of the 8-byte fields at the end of the trampoline.
+unsafe fn write_field(frame: PhysFrame, symbol: *const u8, value: u64) {
+    let addr = memory::phys_to_virt(frame.start_address() + offset_of(symbol) as u64);
+    ptr::write_volatile(addr.as_mut_ptr(), value);
+}
+
+/// Where application processors land once they're in long mode and on their own stack.
+extern "C" fn ap_main(boot_info: &'static ApBootInfo) -> ! {
+    boot_info.tables.load();
+    interrupts::init_idt();
+    percpu::init(boot_info.cpu_id, boot_info.apic_id);
+    apic::init_ap();
+
+    AP_STARTED.store(true, Ordering::SeqCst);
+    CPUS_ONLINE.fetch_add(1, Ordering::AcqRel);
+
+    x86_64::instructions::interrupts::enable();
+
+    Executor::new().run();
+}
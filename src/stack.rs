@@ -0,0 +1,107 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+/// Where the bootloader puts the kernel stack. Must match `kernel-stack-address` in Cargo.toml.
+/// The bootloader leaves the first page of this range unmapped as a guard page.
+pub const KERNEL_STACK_ADDRESS: u64 = 0x_7777_0000_0000;
+/// The size of the kernel stack in pages. Must match `kernel-stack-size` in Cargo.toml.
+pub const KERNEL_STACK_PAGES: u64 = 128;
+
+/// The start of the virtual memory region that stacks allocated by the kernel are mapped into
+const STACK_REGION_START: u64 = 0x_5555_0000_0000;
+
+/// The maximum number of stacks that can be tracked.
+const MAX_STACKS: usize = 32;
+
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACK_REGION_START);
+
+/// Every known stack. The page directly below each one is its guard page.
+static STACKS: Mutex<[Option<Stack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// A stack mapped into virtual memory with an unmapped guard page directly beneath it.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    pub name: &'static str,
+    /// The lowest mapped address of the stack
+    pub bottom: VirtAddr,
+    /// The address that the stack pointer should start at (stacks grow downwards)
+    pub top: VirtAddr,
+}
+
+/// Maps a new stack of `pages` pages, leaving the page below it unmapped.
+/// Overflowing the stack then causes a page fault on the guard page, which
+/// the page fault handler reports as a stack overflow in `name`.
+pub fn alloc_stack(
+    name: &'static str,
+    pages: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<Stack, MapToError<Size4KiB>> {
+    // One extra page for the guard page
+    let start = NEXT_STACK.fetch_add((pages + 1) * Page::<Size4KiB>::SIZE, Ordering::Relaxed);
+
+    let guard_page = Page::containing_address(VirtAddr::new(start));
+    let stack_start = guard_page + 1;
+    let stack_end = stack_start + pages;
+
+    for page in Page::range(stack_start, stack_end) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    let stack = Stack {
+        name,
+        bottom: stack_start.start_address(),
+        top: stack_end.start_address(),
+    };
+
+    register_stack(stack);
+
+    Ok(stack)
+}
+
+/// Records a stack that was mapped elsewhere (i.e. by the bootloader), so that its guard page
+/// is recognised. If too many stacks have been registered, the stack is silently not tracked;
+/// overflows will still fault, but they will be reported as ordinary page faults.
+pub fn register_stack(stack: Stack) {
+    let mut stacks = STACKS.lock();
+
+    if let Some(slot) = stacks.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(stack);
+    }
+}
+
+/// Returns the name of the stack whose guard page contains `addr`, if any.
+/// Safe to call from exception handlers; if the stack list is being modified, this returns None.
+pub fn guard_page_owner(addr: VirtAddr) -> Option<&'static str> {
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let stacks = STACKS.try_lock()?;
+
+    stacks
+        .iter()
+        .flatten()
+        .find(|stack| Page::containing_address(stack.bottom) - 1 == page)
+        .map(|stack| stack.name)
+}
+
+/// Returns the stack that `addr` lies within, if any.
+/// Safe to call from exception handlers; if the stack list is being modified, this returns None.
+pub fn containing(addr: VirtAddr) -> Option<Stack> {
+    let stacks = STACKS.try_lock()?;
+
+    stacks
+        .iter()
+        .flatten()
+        .find(|stack| stack.bottom <= addr && addr < stack.top)
+        .copied()
+}
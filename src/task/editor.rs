@@ -0,0 +1,488 @@
+//! A full-screen text editor.
+//!
+//! The text lives in a gap buffer, so typing and deleting at the cursor don't move the rest of
+//! the file around. The editor takes input focus while it's open, so the shell stops seeing keys
+//! until it's closed.
+//!
+//! Keys: the arrows, Home, End, Page Up and Page Down move the cursor, Ctrl+S saves, Ctrl+F
+//! searches and Ctrl+Q quits.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::font;
+use crate::fs::{Path, FILESYSTEM};
+use crate::task::input::{self, InputEvent};
+use crate::task::keyboard::KeyEvent;
+use crate::vga_buffer::{global_writer, screen_size, BufWrite, Colour, ColourCode, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// The most rows that can be used for text; the last row is the status line
+const TEXT_ROWS: usize = BUFFER_HEIGHT - 1;
+const TAB_WIDTH: usize = 4;
+
+/// Text with a gap at the cursor. Inserting and deleting next to the gap is cheap; moving the
+/// gap costs as much as the distance it moves.
+pub struct GapBuffer {
+    chars: Vec<char>,
+    gap_start: usize,
+    gap_end: usize,
+}
+
+impl GapBuffer {
+    pub fn new(text: &str) -> GapBuffer {
+        let mut chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+
+        chars.resize(len + 64, '\0');
+
+        GapBuffer {
+            chars,
+            gap_start: len,
+            gap_end: len + 64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len() - (self.gap_end - self.gap_start)
+    }
+
+    /// The character at `index`, not counting the gap
+    pub fn get(&self, index: usize) -> Option<char> {
+        if index < self.gap_start {
+            Some(self.chars[index])
+        } else {
+            self.chars.get(index + self.gap_end - self.gap_start).copied()
+        }
+    }
+
+    /// Moves the gap to `index`.
+    fn move_gap(&mut self, index: usize) {
+        let index = index.min(self.len());
+
+        if index < self.gap_start {
+            let moved = self.gap_start - index;
+            self.chars.copy_within(index..self.gap_start, self.gap_end - moved);
+            self.gap_start -= moved;
+            self.gap_end -= moved;
+        } else if index > self.gap_start {
+            let moved = index - self.gap_start;
+            self.chars.copy_within(self.gap_end..self.gap_end + moved, self.gap_start);
+            self.gap_start += moved;
+            self.gap_end += moved;
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, character: char) {
+        self.move_gap(index);
+
+        if self.gap_start == self.gap_end {
+            // Double the buffer, and move everything after the gap to the end
+            let grow = self.chars.len().max(64);
+            let after = self.chars.len() - self.gap_end;
+
+            self.chars.resize(self.chars.len() + grow, '\0');
+            self.chars.copy_within(self.gap_end..self.gap_end + after, self.gap_end + grow);
+            self.gap_end += grow;
+        }
+
+        self.chars[self.gap_start] = character;
+        self.gap_start += 1;
+    }
+
+    /// Removes the character at `index`, if there is one.
+    pub fn remove(&mut self, index: usize) -> Option<char> {
+        if index >= self.len() {
+            return None;
+        }
+
+        self.move_gap(index);
+        self.gap_end += 1;
+
+        Some(self.chars[self.gap_end - 1])
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars[..self.gap_start]
+            .iter()
+            .chain(&self.chars[self.gap_end..])
+            .copied()
+    }
+
+    pub fn text(&self) -> String {
+        self.chars().collect()
+    }
+}
+
+/// What the keyboard is currently editing
+enum Mode {
+    Text,
+    /// Typing a search query into the status line
+    Search(String),
+}
+
+pub struct Editor {
+    path: Path,
+    text: GapBuffer,
+    /// The index of the character that the cursor is in front of
+    cursor: usize,
+    /// The column on screen that up and down try to keep the cursor in, with tabs expanded
+    goal_column: Option<usize>,
+    /// The first line and column on screen
+    top_line: usize,
+    left_column: usize,
+    modified: bool,
+    mode: Mode,
+    last_search: String,
+    /// A message for the status line, cleared by the next key
+    message: Option<String>,
+    /// Set after Ctrl+Q is pressed with unsaved changes, so the next Ctrl+Q quits anyway
+    quit_pending: bool,
+}
+
+impl Editor {
+    /// Opens the file at `path`, or starts a new one if there isn't a file there.
+    pub fn open(path: Path) -> Editor {
+        let (text, message) = match FILESYSTEM.lock().get(&path) {
+            Some(file) => (String::from_utf8_lossy(file.contents()).into_owned(), None),
+            None => (String::new(), Some("New file".into())),
+        };
+
+        Editor {
+            path,
+            text: GapBuffer::new(&text),
+            cursor: 0,
+            goal_column: None,
+            top_line: 0,
+            left_column: 0,
+            modified: false,
+            mode: Mode::Text,
+            last_search: String::new(),
+            message,
+            quit_pending: false,
+        }
+    }
+
+    /// Takes input focus and edits until the user quits. Clears the screen on the way out.
+    pub async fn run(mut self) {
+        let mut input = input::focus();
+
+        self.draw();
+
+        while let Some(event) = input.recv().await {
+            let InputEvent::Key(event) = event else {
+                continue;
+            };
+
+            if !self.handle_key(event) {
+                break;
+            }
+
+            self.draw();
+        }
+
+        global_writer::lock().clear_all();
+    }
+
+    pub fn save(&mut self) {
+        let contents = self.text.text().into_bytes();
+        let len = contents.len();
+
+        FILESYSTEM.lock().create(self.path.clone(), contents);
+
+        self.modified = false;
+        self.message = Some(format!("Saved {len} bytes to {}", self.path));
+    }
+
+    /// Returns `false` once the editor should close.
+    fn handle_key(&mut self, event: KeyEvent) -> bool {
+        let Some(key) = event.key else {
+            return true;
+        };
+
+        self.message = None;
+
+        if let Mode::Search(query) = &mut self.mode {
+            match key {
+                DecodedKey::Unicode('\n') => {
+                    let query = core::mem::take(query);
+                    self.mode = Mode::Text;
+                    self.find(&query);
+                    self.last_search = query;
+                }
+                DecodedKey::Unicode('\u{1b}') => self.mode = Mode::Text,
+                DecodedKey::Unicode('\u{8}') => {
+                    query.pop();
+                }
+                DecodedKey::Unicode(character) if !character.is_control() => query.push(character),
+                _ => {}
+            }
+
+            return true;
+        }
+
+        let quit_pending = core::mem::take(&mut self.quit_pending);
+
+        match key {
+            // Ctrl+Q
+            DecodedKey::Unicode('\u{11}') => {
+                if !self.modified || quit_pending {
+                    return false;
+                }
+
+                self.quit_pending = true;
+                self.message = Some("Unsaved changes; press Ctrl+Q again to quit without saving".into());
+            }
+            // Ctrl+S
+            DecodedKey::Unicode('\u{13}') => self.save(),
+            // Ctrl+F
+            DecodedKey::Unicode('\u{6}') => self.mode = Mode::Search(self.last_search.clone()),
+            DecodedKey::Unicode('\u{8}') => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.delete();
+                }
+            }
+            DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => self.delete(),
+            DecodedKey::Unicode(character) if character == '\n' || character == '\t' || !character.is_control() => {
+                self.text.insert(self.cursor, character);
+                self.cursor += 1;
+                self.modified = true;
+                self.goal_column = None;
+            }
+            DecodedKey::RawKey(code) => self.move_cursor(code),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn delete(&mut self) {
+        if self.text.remove(self.cursor).is_some() {
+            self.modified = true;
+        }
+        self.goal_column = None;
+    }
+
+    fn move_cursor(&mut self, code: KeyCode) {
+        let line_start = self.line_start(self.cursor);
+
+        match code {
+            KeyCode::ArrowLeft => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::ArrowRight => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Home => self.cursor = line_start,
+            KeyCode::End => self.cursor = self.line_end(self.cursor),
+            KeyCode::ArrowUp | KeyCode::ArrowDown | KeyCode::PageUp | KeyCode::PageDown => {
+                let goal = match self.goal_column {
+                    Some(goal) => goal,
+                    None => *self.goal_column.insert(self.display_column(line_start, self.cursor)),
+                };
+                let (lines, up) = match code {
+                    KeyCode::ArrowUp => (1, true),
+                    KeyCode::ArrowDown => (1, false),
+                    KeyCode::PageUp => (text_size().1, true),
+                    _ => (text_size().1, false),
+                };
+
+                let mut start = line_start;
+                for _ in 0..lines {
+                    start = match up {
+                        true if start == 0 => break,
+                        true => self.line_start(start - 1),
+                        false if self.line_end(start) == self.text.len() => break,
+                        false => self.line_end(start) + 1,
+                    };
+                }
+
+                self.cursor = self.index_at_column(start, goal);
+                return;
+            }
+            _ => return,
+        }
+
+        self.goal_column = None;
+    }
+
+    /// Moves the cursor to the next match of `query`, wrapping around to the start.
+    fn find(&mut self, query: &str) {
+        if query.is_empty() {
+            return;
+        }
+
+        let text = self.text.text();
+        // Searching works in bytes, but the cursor counts characters. Start just past the cursor,
+        // so that searching again finds the next match.
+        let after = text.char_indices().nth(self.cursor + 1).map_or(text.len(), |(byte, _)| byte);
+
+        let found = text[after..]
+            .find(query)
+            .map(|byte| after + byte)
+            .or_else(|| text.find(query));
+
+        match found {
+            Some(byte) => {
+                self.cursor = text[..byte].chars().count();
+                self.goal_column = None;
+            }
+            None => self.message = Some(format!("`{query}` not found")),
+        }
+    }
+
+    /// The index of the first character on the line that `index` is on
+    fn line_start(&self, index: usize) -> usize {
+        (0..index).rev().find(|&i| self.text.get(i) == Some('\n')).map_or(0, |i| i + 1)
+    }
+
+    /// The index of the newline at the end of the line that `index` is on
+    fn line_end(&self, index: usize) -> usize {
+        (index..self.text.len()).find(|&i| self.text.get(i) == Some('\n')).unwrap_or(self.text.len())
+    }
+
+    /// The column on screen of the character at `index`, on the line starting at `line_start`
+    fn display_column(&self, line_start: usize, index: usize) -> usize {
+        (line_start..index).filter_map(|i| self.text.get(i)).fold(0, next_column)
+    }
+
+    /// The character on the line starting at `line_start` that covers `column` on screen, or the
+    /// end of the line if it's shorter
+    fn index_at_column(&self, line_start: usize, column: usize) -> usize {
+        let line_end = self.line_end(line_start);
+        let mut current = 0;
+
+        for index in line_start..line_end {
+            let next = self.text.get(index).map_or(current + 1, |character| next_column(current, character));
+
+            if next > column {
+                return index;
+            }
+            current = next;
+        }
+
+        line_end
+    }
+
+    /// The line and column of the cursor, counting tabs as their width on screen
+    fn cursor_position(&self) -> (usize, usize) {
+        let mut line = 0;
+        let mut column = 0;
+
+        for character in self.text.chars().take(self.cursor) {
+            match character {
+                '\n' => {
+                    line += 1;
+                    column = 0;
+                }
+                _ => column = next_column(column, character),
+            }
+        }
+
+        (line, column)
+    }
+
+    /// Scrolls so that the cursor is on screen.
+    fn scroll(&mut self, line: usize, column: usize) {
+        let (columns, rows) = text_size();
+
+        if line < self.top_line {
+            self.top_line = line;
+        } else if line >= self.top_line + rows {
+            self.top_line = line + 1 - rows;
+        }
+
+        if column < self.left_column {
+            self.left_column = column;
+        } else if column >= self.left_column + columns {
+            self.left_column = column + 1 - columns;
+        }
+    }
+
+    fn draw(&mut self) {
+        let (cursor_line, cursor_column) = self.cursor_position();
+        self.scroll(cursor_line, cursor_column);
+
+        let text_colour = ColourCode::default();
+        let status_colour = ColourCode::new(Colour::Black, Colour::Cyan);
+        let (columns, rows) = text_size();
+
+        let mut screen = global_writer::lock();
+        let mut cells = [[b' '; BUFFER_WIDTH]; TEXT_ROWS];
+
+        let mut line: usize = 0;
+        let mut column: usize = 0;
+
+        for character in self.text.chars() {
+            if character == '\n' {
+                line += 1;
+                column = 0;
+                continue;
+            }
+
+            let width = next_column(column, character) - column;
+
+            if let Some(row) = line.checked_sub(self.top_line).filter(|&row| row < rows) {
+                for offset in 0..width {
+                    if let Some(col) = (column + offset).checked_sub(self.left_column).filter(|&col| col < columns) {
+                        cells[row][col] = match character {
+                            '\t' => b' ',
+                            _ => font::glyph(character),
+                        };
+                    }
+                }
+            }
+
+            column += width;
+        }
+
+        for (row, cells) in cells.iter().take(rows).enumerate() {
+            for (col, &byte) in cells.iter().take(columns).enumerate() {
+                screen.buffer.write_char(text_colour, byte, row, col).ok();
+            }
+        }
+
+        let status = match &self.mode {
+            Mode::Search(query) => format!(" Search: {query}"),
+            Mode::Text => {
+                let modified = if self.modified { " [modified]" } else { "" };
+                let position = format!("{}:{}", cursor_line + 1, cursor_column + 1);
+                let left = match &self.message {
+                    Some(message) => format!(" {}{modified} - {message}", self.path),
+                    None => format!(" {}{modified}", self.path),
+                };
+
+                format!("{left:<width$}{position} ", width = columns.saturating_sub(position.len() + 1))
+            }
+        };
+
+        let mut status = status.chars();
+        for col in 0..columns {
+            let byte = status.next().map_or(b' ', font::glyph);
+
+            screen.buffer.write_char(status_colour, byte, rows, col).ok();
+        }
+
+        // The blinking cursor goes after the query while searching
+        match &self.mode {
+            Mode::Search(query) => screen.set_position(rows, " Search: ".len() + query.chars().count()),
+            Mode::Text => screen.set_position(cursor_line - self.top_line, cursor_column - self.left_column),
+        }
+
+        screen.draw_mouse_cursor();
+    }
+}
+
+/// The column after `character`, if it starts at `column`. Tabs go to the next tab stop.
+fn next_column(column: usize, character: char) -> usize {
+    match character {
+        '\t' => column + TAB_WIDTH - column % TAB_WIDTH,
+        _ => column + 1,
+    }
+}
+
+/// The columns and rows there's room for on screen, leaving the last row for the status line
+fn text_size() -> (usize, usize) {
+    let (columns, rows) = screen_size();
+
+    (columns, rows - 1)
+}
@@ -0,0 +1,143 @@
+//! The input bus: input drivers publish events, and tasks receive them as async streams.
+//!
+//! Tasks can listen in two ways. A subscription sees every event, whatever else is listening.
+//! Focus is exclusive: each virtual terminal has a stack of focused streams, and only the one
+//! on top of the active terminal's stack gets events, until it's dropped and focus goes back to
+//! the one below. The shell takes focus when it starts, and anything that takes over the screen
+//! (an editor, a TUI window) takes focus on top of it.
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+use alloc::vec::Vec;
+use futures_util::Stream;
+use spin::Mutex;
+
+use super::keyboard::KeyEvent;
+use super::mouse::MouseEvent;
+use crate::runtime::sync::mpsc::{self, TrySendError};
+use crate::vt::{self, VT_COUNT};
+
+/// How many events a listener can fall behind by before events get dropped
+const LISTENER_CAPACITY: usize = 64;
+
+static BUS: Mutex<Bus> = Mutex::new(Bus {
+    subscribers: Vec::new(),
+    focus: [const { Vec::new() }; VT_COUNT],
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+struct Listener {
+    id: u64,
+    sender: mpsc::Sender<InputEvent>,
+    /// Set while events are being dropped, so that the warning only shows once
+    dropping: bool,
+}
+
+struct Bus {
+    subscribers: Vec<Listener>,
+    /// The focus stack of each terminal; the last listener has focus
+    focus: [Vec<Listener>; VT_COUNT],
+}
+
+/// Sends an event to every subscriber and to whoever has focus on the active terminal.
+pub fn publish(event: InputEvent) {
+    let mut bus = BUS.lock();
+    let Bus { subscribers, focus } = &mut *bus;
+    let focus = &mut focus[vt::active()];
+
+    subscribers.retain_mut(|listener| deliver(listener, event));
+
+    // A focused listener that has gone away hands focus on to the one below it
+    while let Some(listener) = focus.last_mut() {
+        if deliver(listener, event) {
+            break;
+        }
+        focus.pop();
+    }
+}
+
+/// Returns `false` if the listener has gone away.
+fn deliver(listener: &mut Listener, event: InputEvent) -> bool {
+    match listener.sender.try_send(event) {
+        Ok(()) => {
+            listener.dropping = false;
+            true
+        }
+        Err(TrySendError::Full(_)) => {
+            if !core::mem::replace(&mut listener.dropping, true) {
+                log::warn!("input listener {} is falling behind; dropping input", listener.id);
+            }
+            true
+        }
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+/// Starts receiving every input event.
+pub fn subscribe() -> InputStream {
+    let (listener, stream) = listener();
+    BUS.lock().subscribers.push(listener);
+
+    stream
+}
+
+/// Takes exclusive input focus on the current task's terminal until the returned stream is
+/// dropped.
+pub fn focus() -> InputStream {
+    let (listener, stream) = listener();
+    BUS.lock().focus[vt::current()].push(listener);
+
+    stream
+}
+
+fn listener() -> (Listener, InputStream) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::channel(LISTENER_CAPACITY);
+
+    (Listener { id, sender, dropping: false }, InputStream { id, receiver })
+}
+
+/// A stream of input events, from either `subscribe` or `focus`.
+pub struct InputStream {
+    id: u64,
+    receiver: mpsc::Receiver<InputEvent>,
+}
+
+impl InputStream {
+    pub async fn recv(&mut self) -> Option<InputEvent> {
+        self.receiver.recv().await
+    }
+
+    /// Whether this stream is the one with focus on the active terminal
+    pub fn has_focus(&self) -> bool {
+        BUS.lock().focus[vt::active()].last().is_some_and(|listener| listener.id == self.id)
+    }
+}
+
+impl Stream for InputStream {
+    type Item = InputEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<InputEvent>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for InputStream {
+    fn drop(&mut self) {
+        let mut bus = BUS.lock();
+
+        bus.subscribers.retain_mut(|listener| listener.id != self.id);
+        for focus in &mut bus.focus {
+            focus.retain(|listener| listener.id != self.id);
+        }
+    }
+}
@@ -2,7 +2,6 @@ use core::{
     pin::Pin,
     task::{Context, Poll},
 };
-use core::fmt::Write;
 
 use conquer_once::spin::OnceCell;
 use crossbeam::queue::ArrayQueue;
@@ -12,10 +11,6 @@ static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
 use futures_util::task::AtomicWaker;
 
-use crate::{
-    vga_buffer::{Colour, ColourCode, ColourText, global_writer},
-};
-
 static WAKER: AtomicWaker = AtomicWaker::new();
 
 pub struct ScancodeStream {
@@ -23,14 +18,19 @@ pub struct ScancodeStream {
 }
 
 impl ScancodeStream {
+    /// Only the keyboard task reads the queue, so there should only be one of these.
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
+        init();
         ScancodeStream { _private: () }
     }
 }
 
+/// Creates the scancode queue. Called before the keyboard interrupt is unmasked, so that keys
+/// pressed during boot wait there for the keyboard task.
+pub fn init() {
+    SCANCODE_QUEUE.init_once(|| ArrayQueue::new(100));
+}
+
 impl Stream for ScancodeStream {
     type Item = u8;
 
@@ -54,31 +54,219 @@ impl Stream for ScancodeStream {
 }
 
 pub(crate) fn add_scancode(scancode: u8) {
-    let warn = ColourText::colour(ColourCode::new(Colour::Black, Colour::Yellow), "WARNING:");
+    let Ok(queue) = SCANCODE_QUEUE.try_get() else {
+        log::warn!("scancode queue not created yet; dropping keyboard input");
+        return;
+    };
 
-    let queue = SCANCODE_QUEUE.try_get().expect("Input queue uninitialized");
-
-    if let Err(_) = queue.push(scancode) {
-        writeln!(global_writer::maybe(), "{warn}: scancode queue full; dropping keyboard input").ok();
+    if queue.push(scancode).is_err() {
+        log::warn!("scancode queue full; dropping keyboard input");
     } else {
         WAKER.wake()
     }
 }
 
 use futures_util::stream::StreamExt;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use pc_keyboard::{DecodeState, DecodedKey, HandleControl, KeyCode, KeyState, Modifiers, ScancodeSet, ScancodeSet1};
+use spin::Mutex;
+
+use super::input::{self, InputEvent};
+use super::keymap::Layout;
+use crate::ps2::{self, Leds};
+use crate::vga_buffer::screen_size;
+use crate::vt;
+
+/// The keyboard's state: the scancode decoder, the modifier keys and the layout. Only the
+/// keyboard task feeds it scancodes; everything else just reads or changes the settings.
+static KEYBOARD: Mutex<KeyboardState> = Mutex::new(KeyboardState {
+    decode_state: DecodeState::Start,
+    modifiers: Modifiers {
+        lshift: false,
+        rshift: false,
+        lctrl: false,
+        rctrl: false,
+        numlock: true,
+        capslock: false,
+        alt_gr: false,
+    },
+    alt: false,
+    scroll_lock: false,
+    leds_changed: false,
+    layout: Layout::Us104,
+    control_mode: ControlMode::ControlCharacters,
+});
+
+/// What Ctrl + a letter produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMode {
+    /// The ASCII control character, so Ctrl+C is U+0003
+    ControlCharacters,
+    /// The letter itself, with `KeyModifiers::ctrl` set
+    Chords,
+}
+
+/// The modifier keys that were held when a key was pressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+}
+
+/// A key going down or up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// What the key means in the current layout. Only set when a key that isn't a modifier or
+    /// lock key is pressed.
+    pub key: Option<DecodedKey>,
+    /// The modifiers held after this event
+    pub modifiers: KeyModifiers,
+}
+
+struct KeyboardState {
+    decode_state: DecodeState,
+    modifiers: Modifiers,
+    /// The left Alt key; pc-keyboard only tracks the right one, as AltGr
+    alt: bool,
+    scroll_lock: bool,
+    /// Set when a lock key is toggled, until the LEDs are updated
+    leds_changed: bool,
+    layout: Layout,
+    control_mode: ControlMode,
+}
+
+impl KeyboardState {
+    fn add_scancode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let event = ScancodeSet1::advance_state(&mut self.decode_state, scancode).ok()??;
+        let down = event.state == KeyState::Down;
+        let mut key = None;
+
+        match event.code {
+            KeyCode::ShiftLeft => self.modifiers.lshift = down,
+            KeyCode::ShiftRight => self.modifiers.rshift = down,
+            KeyCode::ControlLeft => self.modifiers.lctrl = down,
+            KeyCode::ControlRight => self.modifiers.rctrl = down,
+            KeyCode::AltLeft => self.alt = down,
+            KeyCode::AltRight => self.modifiers.alt_gr = down,
+            KeyCode::CapsLock if down => {
+                self.modifiers.capslock = !self.modifiers.capslock;
+                self.leds_changed = true;
+            }
+            KeyCode::NumpadLock if down => {
+                self.modifiers.numlock = !self.modifiers.numlock;
+                self.leds_changed = true;
+            }
+            KeyCode::ScrollLock if down => {
+                self.scroll_lock = !self.scroll_lock;
+                self.leds_changed = true;
+            }
+            code if down => {
+                let handle_ctrl = match self.control_mode {
+                    ControlMode::ControlCharacters => HandleControl::MapLettersToUnicode,
+                    ControlMode::Chords => HandleControl::Ignore,
+                };
+
+                key = Some(self.layout.map_keycode(code, &self.modifiers, handle_ctrl));
+            }
+            _ => {}
+        }
+
+        Some(KeyEvent {
+            code: event.code,
+            state: event.state,
+            key,
+            modifiers: KeyModifiers {
+                shift: self.modifiers.is_shifted(),
+                ctrl: self.modifiers.is_ctrl(),
+                alt: self.alt,
+                alt_gr: self.modifiers.alt_gr,
+            },
+        })
+    }
+
+    /// The LEDs to show, if a lock key was toggled since the last call
+    fn take_leds(&mut self) -> Option<Leds> {
+        if !core::mem::take(&mut self.leds_changed) {
+            return None;
+        }
 
-pub async fn handle_keypresses(
-    press_handler: impl Fn(DecodedKey),
-) {
+        Some(Leds {
+            scroll_lock: self.scroll_lock,
+            num_lock: self.modifiers.numlock,
+            caps_lock: self.modifiers.capslock,
+        })
+    }
+}
+
+/// Switches to another keyboard layout. Takes effect from the next key press.
+pub fn set_layout(layout: Layout) {
+    KEYBOARD.lock().layout = layout;
+}
+
+pub fn layout() -> Layout {
+    KEYBOARD.lock().layout.clone()
+}
+
+pub fn set_control_mode(control_mode: ControlMode) {
+    KEYBOARD.lock().control_mode = control_mode;
+}
+
+/// Decodes scancodes and publishes them on the input bus as key events.
+pub async fn handle_keypresses() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                press_handler(key)
-            }
+        let (event, leds) = {
+            let mut keyboard = KEYBOARD.lock();
+            (keyboard.add_scancode(scancode), keyboard.take_leds())
+        };
+
+        if let Some(leds) = leds {
+            // Failures are already reported, and the lock state is right even if the LEDs aren't
+            ps2::set_leds(leds).ok();
+        }
+
+        let Some(event) = event else {
+            continue;
+        };
+
+        if event.state == KeyState::Down && handle_terminal_keys(&event) {
+            continue;
         }
+
+        // Typing brings a terminal that's scrolled back down to where the typing shows up
+        if event.key.is_some() {
+            vt::scroll_to_bottom();
+        }
+
+        input::publish(InputEvent::Key(event));
     }
 }
+
+/// Handles the keys that switch and scroll virtual terminals. Returns `false` for any other key.
+fn handle_terminal_keys(event: &KeyEvent) -> bool {
+    const FUNCTION_KEYS: [KeyCode; vt::VT_COUNT] =
+        [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6];
+
+    let scroll_lines = screen_size().1 as isize / 2;
+
+    let modifiers = event.modifiers;
+
+    if modifiers.alt && !modifiers.ctrl {
+        if let Some(vt) = FUNCTION_KEYS.iter().position(|&code| code == event.code) {
+            vt::switch(vt);
+            return true;
+        }
+    }
+
+    match event.code {
+        KeyCode::PageUp if modifiers.shift => vt::scroll(scroll_lines),
+        KeyCode::PageDown if modifiers.shift => vt::scroll(-scroll_lines),
+        _ => return false,
+    }
+
+    true
+}
@@ -0,0 +1,314 @@
+use core::fmt;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+
+use crate::fs::{Path, FILESYSTEM};
+
+/// Where keymap files live in the filesystem
+const KEYMAP_DIR: &str = "/keymaps";
+
+/// A keyboard layout that can be switched at runtime: either one of pc-keyboard's built-in
+/// layouts, or a keymap loaded from a file.
+#[derive(Clone)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    Azerty,
+    Dvorak104,
+    Jis109,
+    Keymap(Arc<Keymap>),
+}
+
+impl Layout {
+    /// The names of the built-in layouts, as accepted by `Layout::load`
+    pub const BUILT_IN: &'static [&'static str] = &["us", "uk", "fr", "dvorak", "jis"];
+
+    fn built_in(name: &str) -> Option<Layout> {
+        Some(match name {
+            "us" => Layout::Us104,
+            "uk" => Layout::Uk105,
+            "fr" => Layout::Azerty,
+            "dvorak" => Layout::Dvorak104,
+            "jis" => Layout::Jis109,
+            _ => return None,
+        })
+    }
+
+    /// Looks up a built-in layout, or loads `/keymaps/<name>.keymap`.
+    pub fn load(name: &str) -> Result<Layout, KeymapError> {
+        if let Some(layout) = Layout::built_in(name) {
+            return Ok(layout);
+        }
+
+        let path = Path::new(&alloc::format!("{KEYMAP_DIR}/{name}.keymap"));
+        let filesystem = FILESYSTEM.lock();
+        let file = filesystem.get(&path).ok_or(KeymapError::NotFound)?;
+        let text = core::str::from_utf8(file.contents()).map_err(|_| KeymapError::NotUtf8)?;
+
+        Ok(Layout::Keymap(Arc::new(Keymap::parse(name, text)?)))
+    }
+
+    /// The names of the keymap files in the filesystem
+    pub fn keymap_files() -> Vec<String> {
+        FILESYSTEM
+            .lock()
+            .paths()
+            .filter_map(|path| {
+                let path = alloc::format!("{path}");
+                let name = path.strip_prefix(KEYMAP_DIR)?.strip_prefix('/')?;
+                Some(name.strip_suffix(".keymap")?.into())
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Layout::Us104 => "us",
+            Layout::Uk105 => "uk",
+            Layout::Azerty => "fr",
+            Layout::Dvorak104 => "dvorak",
+            Layout::Jis109 => "jis",
+            Layout::Keymap(keymap) => &keymap.name,
+        }
+    }
+
+    pub fn map_keycode(&self, keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+        match self {
+            Layout::Us104 => layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk105 => layouts::Uk105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Azerty => layouts::Azerty::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak104 => layouts::Dvorak104Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Jis109 => layouts::Jis109Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Keymap(keymap) => keymap.map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+/// A layout loaded from a keymap file. Each line of the file maps one key:
+///
+/// ```text
+/// # Comments start with a hash
+/// base us             # Keys that aren't listed come from this built-in layout
+/// Z  y  Y             # key name, then the character without and with shift
+/// Q  q  Q  @          # and optionally with AltGr
+/// Key2 2 U+0022 U+00B2
+/// ```
+///
+/// Characters can be written as themselves or as `U+XXXX`, and `-` leaves a slot empty so that
+/// it falls back to the base layout. A field starting with `#` starts a comment, so `#` itself
+/// has to be written as `U+0023`. Key names are pc-keyboard's `KeyCode` names.
+pub struct Keymap {
+    name: String,
+    base: Layout,
+    keys: Vec<(KeyCode, KeymapEntry)>,
+}
+
+#[derive(Clone, Copy)]
+struct KeymapEntry {
+    normal: Option<char>,
+    shifted: Option<char>,
+    alt_gr: Option<char>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    NotFound,
+    NotUtf8,
+    UnknownKey { line: usize, name: String },
+    /// A key was listed without any characters for it
+    NoCharacters { line: usize, name: String },
+    InvalidCharacter { line: usize, character: String },
+    /// The base layout has to be one of the built-in layouts
+    UnknownBase { line: usize, name: String },
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapError::NotFound => write!(f, "no such layout"),
+            KeymapError::NotUtf8 => write!(f, "the keymap file isn't valid UTF-8"),
+            KeymapError::UnknownKey { line, name } => write!(f, "line {line}: unknown key `{name}`"),
+            KeymapError::NoCharacters { line, name } => write!(f, "line {line}: no characters for `{name}`"),
+            KeymapError::InvalidCharacter { line, character } => {
+                write!(f, "line {line}: `{character}` isn't a single character or U+XXXX")
+            }
+            KeymapError::UnknownBase { line, name } => {
+                write!(f, "line {line}: `{name}` isn't a built-in layout")
+            }
+        }
+    }
+}
+
+impl Keymap {
+    pub fn parse(name: &str, text: &str) -> Result<Keymap, KeymapError> {
+        let mut keymap = Keymap {
+            name: name.into(),
+            base: Layout::Us104,
+            keys: Vec::new(),
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let mut fields = line.split_whitespace().take_while(|field| !field.starts_with('#')).peekable();
+
+            let Some(first) = fields.next() else {
+                continue;
+            };
+
+            if first == "base" {
+                let base = fields.next().unwrap_or_default();
+                keymap.base = Layout::built_in(base).ok_or_else(|| KeymapError::UnknownBase {
+                    line: line_number,
+                    name: base.into(),
+                })?;
+                continue;
+            }
+
+            let keycode = keycode_by_name(first).ok_or_else(|| KeymapError::UnknownKey {
+                line: line_number,
+                name: first.into(),
+            })?;
+
+            if fields.peek().is_none() {
+                return Err(KeymapError::NoCharacters {
+                    line: line_number,
+                    name: first.into(),
+                });
+            }
+
+            let mut next_char = || parse_char(fields.next(), line_number);
+
+            let entry = KeymapEntry {
+                normal: next_char()?,
+                shifted: next_char()?,
+                alt_gr: next_char()?,
+            };
+
+            keymap.keys.retain(|(existing, _)| *existing != keycode);
+            keymap.keys.push((keycode, entry));
+        }
+
+        Ok(keymap)
+    }
+
+    fn map_keycode(&self, keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+        let fallback = || self.base.map_keycode(keycode, modifiers, handle_ctrl);
+
+        let Some((_, entry)) = self.keys.iter().find(|(code, _)| *code == keycode) else {
+            return fallback();
+        };
+
+        let character = if modifiers.alt_gr {
+            entry.alt_gr
+        } else {
+            // Caps lock only affects letters
+            let letter = entry.normal.is_some_and(char::is_alphabetic);
+            let shifted = modifiers.is_shifted() ^ (letter && modifiers.capslock);
+
+            if shifted { entry.shifted } else { entry.normal }
+        };
+
+        match character {
+            Some(character) if handle_ctrl == HandleControl::MapLettersToUnicode
+                && modifiers.is_ctrl()
+                && character.is_ascii_alphabetic() =>
+            {
+                DecodedKey::Unicode(((character as u8) & 0x1f) as char)
+            }
+            Some(character) => DecodedKey::Unicode(character),
+            None => fallback(),
+        }
+    }
+}
+
+/// Parses one character field of a keymap line. A missing field or `-` is an empty slot.
+fn parse_char(field: Option<&str>, line: usize) -> Result<Option<char>, KeymapError> {
+    let field = match field {
+        None | Some("-") => return Ok(None),
+        Some(field) => field,
+    };
+
+    let invalid = || KeymapError::InvalidCharacter {
+        line,
+        character: field.into(),
+    };
+
+    if let Some(hex) = field.strip_prefix("U+") {
+        let code = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        return char::from_u32(code).map(Some).ok_or_else(invalid);
+    }
+
+    let mut chars = field.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(character), None) => Ok(Some(character)),
+        _ => Err(invalid()),
+    }
+}
+
+fn keycode_by_name(name: &str) -> Option<KeyCode> {
+    KEYCODE_NAMES
+        .iter()
+        .find(|(keycode_name, _)| *keycode_name == name)
+        .map(|(_, keycode)| *keycode)
+}
+
+/// The keys that a keymap can remap. Modifier and lock keys are left out, since the keyboard
+/// driver handles them itself.
+const KEYCODE_NAMES: &[(&str, KeyCode)] = &[
+    ("BackTick", KeyCode::BackTick),
+    ("Key1", KeyCode::Key1),
+    ("Key2", KeyCode::Key2),
+    ("Key3", KeyCode::Key3),
+    ("Key4", KeyCode::Key4),
+    ("Key5", KeyCode::Key5),
+    ("Key6", KeyCode::Key6),
+    ("Key7", KeyCode::Key7),
+    ("Key8", KeyCode::Key8),
+    ("Key9", KeyCode::Key9),
+    ("Key0", KeyCode::Key0),
+    ("Minus", KeyCode::Minus),
+    ("Equals", KeyCode::Equals),
+    ("Q", KeyCode::Q),
+    ("W", KeyCode::W),
+    ("E", KeyCode::E),
+    ("R", KeyCode::R),
+    ("T", KeyCode::T),
+    ("Y", KeyCode::Y),
+    ("U", KeyCode::U),
+    ("I", KeyCode::I),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("BracketSquareLeft", KeyCode::BracketSquareLeft),
+    ("BracketSquareRight", KeyCode::BracketSquareRight),
+    ("BackSlash", KeyCode::BackSlash),
+    ("A", KeyCode::A),
+    ("S", KeyCode::S),
+    ("D", KeyCode::D),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("SemiColon", KeyCode::SemiColon),
+    ("Quote", KeyCode::Quote),
+    ("HashTilde", KeyCode::HashTilde),
+    ("Z", KeyCode::Z),
+    ("X", KeyCode::X),
+    ("C", KeyCode::C),
+    ("V", KeyCode::V),
+    ("B", KeyCode::B),
+    ("N", KeyCode::N),
+    ("M", KeyCode::M),
+    ("Comma", KeyCode::Comma),
+    ("Fullstop", KeyCode::Fullstop),
+    ("Slash", KeyCode::Slash),
+    ("Spacebar", KeyCode::Spacebar),
+    ("NumpadSlash", KeyCode::NumpadSlash),
+    ("NumpadStar", KeyCode::NumpadStar),
+    ("NumpadMinus", KeyCode::NumpadMinus),
+    ("NumpadPlus", KeyCode::NumpadPlus),
+    ("NumpadPeriod", KeyCode::NumpadPeriod),
+];
@@ -1 +1,7 @@
-pub mod keyboard;
\ No newline at end of file
+pub mod editor;
+pub mod input;
+pub mod keyboard;
+pub mod keymap;
+pub mod monitor;
+pub mod mouse;
+pub mod shell;
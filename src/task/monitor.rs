@@ -0,0 +1,151 @@
+//! A full-screen task monitor, built with the TUI toolkit. Type into the filter to only show
+//! tasks whose names contain it, Tab over to the list to scroll through them, and press Escape
+//! to leave. The figures are refreshed with every key.
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use pc_keyboard::DecodedKey;
+
+use crate::allocator;
+use crate::runtime::executor::Executor;
+use crate::smp;
+use crate::task::input::{self, InputEvent};
+use crate::task::keyboard::KeyEvent;
+use crate::time;
+use crate::tui::{self, theme, Canvas, Constraint, Direction, ListView, ProgressBar, Rect, Screen, StatusBar, TextInput, Widget, Window};
+use crate::vga_buffer::global_writer;
+
+const FILTER_LABEL: &str = "Filter: ";
+
+pub struct Monitor {
+    filter: TextInput,
+    tasks: ListView,
+    heap: ProgressBar,
+    status: StatusBar,
+    focused: Option<usize>,
+    /// The window, the filter, the list header, the list, the heap bar and the status bar
+    areas: Vec<Rect>,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            filter: TextInput::new(),
+            tasks: ListView::new(),
+            heap: ProgressBar::new("Heap"),
+            status: StatusBar::new(),
+            focused: None,
+            areas: Vec::new(),
+        }
+    }
+
+    /// Reads the current task list and heap usage.
+    pub fn refresh(&mut self) {
+        let filter = self.filter.text();
+
+        let tasks = Executor::tasks()
+            .into_iter()
+            .filter(|task| task.name.as_deref().unwrap_or("").contains(filter.as_str()))
+            .map(|task| {
+                format!(
+                    "{:>4} {:<11} {:<8} {:>7} {:>10}  {}",
+                    task.id,
+                    format!("{:?}", task.priority),
+                    format!("{:?}", task.state),
+                    task.polls,
+                    format!("{:.1?}", task.busy),
+                    task.name.as_deref().unwrap_or("<unnamed>"),
+                )
+            })
+            .collect();
+        self.tasks.set_items(tasks);
+
+        let (used, size) = allocator::heap_usage();
+        self.heap.set_progress(used as u64, size as u64);
+
+        self.status.set_left("Tab: switch focus  Esc: quit");
+        self.status.set_right(&format!("{} CPUs  up {:.0?}", smp::cpus_online(), time::uptime()));
+    }
+}
+
+impl Widget for Monitor {
+    fn layout(&mut self, area: Rect) {
+        let outer = Rect::new(0, 0, area.width, area.height).split(Direction::Vertical, &[Constraint::Fill, Constraint::Fixed(1)]);
+        let inner = outer[0].inset(1).split(
+            Direction::Vertical,
+            &[Constraint::Fixed(1), Constraint::Fixed(1), Constraint::Fill, Constraint::Fixed(1)],
+        );
+
+        self.areas = Vec::from([outer[0], inner[0], inner[1], inner[2], inner[3], outer[1]]);
+
+        self.tasks.layout(inner[2]);
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        let [window, filter, header, list, heap, status] = self.areas[..] else {
+            return;
+        };
+
+        Window::draw_frame(&mut canvas.sub(window), "Tasks", self.has_focus());
+
+        let mut filter_canvas = canvas.sub(filter);
+        let label_width = filter_canvas.text(0, 0, FILTER_LABEL, theme::WINDOW);
+        let input_area = Rect::new(label_width, 0, filter.width.saturating_sub(label_width), 1);
+        self.filter.draw(&mut filter_canvas.sub(input_area));
+
+        let header_text = format!("{:>4} {:<11} {:<8} {:>7} {:>10}  NAME", "ID", "PRIORITY", "STATE", "POLLS", "CPU TIME");
+        canvas.sub(header).text(0, 0, &header_text, theme::WINDOW);
+
+        self.tasks.draw(&mut canvas.sub(list));
+        self.heap.draw(&mut canvas.sub(heap));
+        self.status.draw(&mut canvas.sub(status));
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        match self.focused {
+            Some(0) => self.filter.handle_key(key),
+            Some(_) => self.tasks.handle_key(key),
+            None => false,
+        }
+    }
+
+    fn focus_next(&mut self) -> bool {
+        tui::focus_next_child(&mut [&mut self.filter, &mut self.tasks], &mut self.focused)
+    }
+
+    fn has_focus(&self) -> bool {
+        self.focused.is_some()
+    }
+}
+
+/// Takes input focus and shows the monitor until Escape is pressed.
+pub async fn run() {
+    let mut input = input::focus();
+    let mut screen = Screen::new(Monitor::new());
+
+    // The filter draws its own cursor
+    let cursor_shape = global_writer::lock().cursor_shape();
+    global_writer::lock().set_cursor_shape(None);
+
+    screen.root_mut().refresh();
+    screen.draw();
+
+    while let Some(event) = input.recv().await {
+        let InputEvent::Key(key) = event else {
+            continue;
+        };
+
+        if key.key == Some(DecodedKey::Unicode('\u{1b}')) {
+            break;
+        }
+
+        screen.handle_key(&key);
+        screen.root_mut().refresh();
+        screen.draw();
+    }
+
+    let mut writer = global_writer::lock();
+    writer.clear_all();
+    writer.set_cursor_shape(cursor_shape);
+}
@@ -0,0 +1,184 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use conquer_once::spin::OnceCell;
+use crossbeam::queue::ArrayQueue;
+use futures_util::Stream;
+use futures_util::task::AtomicWaker;
+
+use super::input::{self, InputEvent};
+use crate::ps2;
+use crate::vt;
+use crate::vga_buffer::screen_size;
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// How far the mouse has to move, in its own units, to move the cursor by one cell. Cells are
+/// twice as tall as they are wide.
+const COUNTS_PER_COLUMN: i32 = 8;
+const COUNTS_PER_ROW: i32 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// One packet's worth of mouse movement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Positive is to the right
+    pub dx: i16,
+    /// Positive is down, like screen coordinates (the mouse itself reports up as positive)
+    pub dy: i16,
+    /// Positive is towards the user. Always 0 for mice without a scroll wheel.
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+/// Decodes mouse bytes into events. Like `ScancodeStream`, there can only be one.
+pub struct MouseEventStream {
+    packet: [u8; 4],
+    len: usize,
+    packet_size: usize,
+}
+
+impl MouseEventStream {
+    /// Returns `None` if there's no mouse.
+    pub fn new() -> Option<Self> {
+        let packet_size = ps2::mouse_packet_size()?;
+
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("MouseEventStream::new should only be called once");
+
+        Some(MouseEventStream {
+            packet: [0; 4],
+            len: 0,
+            packet_size,
+        })
+    }
+
+    /// Adds a byte to the packet being assembled, returning the event once it's complete.
+    fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 of the first byte is always set; if it isn't, a byte went missing, so skip ahead
+        // until the packets line up again.
+        if self.len == 0 && byte & 0b1000 == 0 {
+            return None;
+        }
+
+        self.packet[self.len] = byte;
+        self.len += 1;
+
+        if self.len < self.packet_size {
+            return None;
+        }
+
+        self.len = 0;
+        decode(&self.packet[..self.packet_size])
+    }
+}
+
+/// Turns a complete packet into an event. Packets whose movement overflowed are dropped.
+fn decode(packet: &[u8]) -> Option<MouseEvent> {
+    let flags = packet[0];
+
+    if flags & 0b1100_0000 != 0 {
+        return None;
+    }
+
+    // The movement is 9-bit two's complement, with the sign bits in the first byte
+    let dx = packet[1] as i16 - (((flags as i16) << 4) & 0x100);
+    let dy = packet[2] as i16 - (((flags as i16) << 3) & 0x100);
+
+    // The low 4 bits of the fourth byte are the wheel movement, also two's complement
+    let wheel = packet.get(3).map_or(0, |&byte| ((byte << 4) as i8) >> 4);
+
+    Some(MouseEvent {
+        dx,
+        dy: -dy,
+        wheel,
+        buttons: MouseButtons {
+            left: flags & 0b001 != 0,
+            right: flags & 0b010 != 0,
+            middle: flags & 0b100 != 0,
+        },
+    })
+}
+
+impl Stream for MouseEventStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queue = BYTE_QUEUE
+            .try_get()
+            .expect("Mouse input queue not initialized");
+
+        loop {
+            let byte = match queue.pop() {
+                Some(byte) => byte,
+                None => {
+                    WAKER.register(cx.waker());
+
+                    match queue.pop() {
+                        Some(byte) => {
+                            WAKER.take();
+                            byte
+                        }
+                        None => return Poll::Pending,
+                    }
+                }
+            };
+
+            if let Some(event) = self.add_byte(byte) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+}
+
+pub(crate) fn add_byte(byte: u8) {
+    // Bytes can arrive before anything listens for them
+    let Ok(queue) = BYTE_QUEUE.try_get() else {
+        return;
+    };
+
+    if queue.push(byte).is_err() {
+        log::warn!("mouse queue full; dropping mouse input");
+    } else {
+        WAKER.wake()
+    }
+}
+
+use futures_util::stream::StreamExt;
+
+/// Publishes mouse events on the input bus, and moves the text-mode mouse cursor to follow
+/// them. Returns straight away if there's no mouse.
+pub async fn handle_mouse_events() {
+    let Some(mut events) = MouseEventStream::new() else {
+        return;
+    };
+
+    // In mouse units, so that slow movements still add up to a whole cell
+    let (columns, rows) = screen_size();
+    let (columns, rows) = (columns as i32, rows as i32);
+
+    let mut x = columns / 2 * COUNTS_PER_COLUMN;
+    let mut y = rows / 2 * COUNTS_PER_ROW;
+
+    while let Some(event) = events.next().await {
+        input::publish(InputEvent::Mouse(event));
+
+        x = (x + event.dx as i32).clamp(0, columns * COUNTS_PER_COLUMN - 1);
+        y = (y + event.dy as i32).clamp(0, rows * COUNTS_PER_ROW - 1);
+
+        let position = ((y / COUNTS_PER_ROW) as usize, (x / COUNTS_PER_COLUMN) as usize);
+
+        vt::lock(vt::active()).set_mouse_cursor(Some(position));
+    }
+}
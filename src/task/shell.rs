@@ -0,0 +1,429 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use core::cmp::Reverse;
+use core::fmt::Write;
+
+use futures_util::future::BoxFuture;
+use log::LevelFilter;
+use pc_keyboard::DecodedKey;
+
+use crate::font;
+use crate::fs::Path;
+use crate::logger;
+use crate::power;
+use crate::ps2;
+use crate::runtime::executor::{Executor, TaskInfo};
+use crate::smp;
+use crate::task::editor::Editor;
+use crate::task::input::{self, InputEvent};
+use crate::task::keyboard::{self, KeyEvent};
+use crate::task::keymap::Layout;
+use crate::task::monitor;
+use crate::time;
+use crate::vga_buffer::{global_writer, CursorShape};
+
+const PROMPT: &str = "> ";
+
+/// A built-in shell command
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    /// Runs the command with everything after its name
+    run: Action,
+}
+
+enum Action {
+    /// Runs straight away
+    Now(fn(args: &str)),
+    /// Starts a task that the shell waits for. The task can take input focus while it runs.
+    Task(fn(args: &str) -> BoxFuture<'static, ()>),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "Lists the available commands",
+        run: Action::Now(help),
+    },
+    Command {
+        name: "clear",
+        help: "Clears the screen",
+        run: Action::Now(|_| global_writer::lock().clear_all()),
+    },
+    Command {
+        name: "dmesg",
+        help: "Shows the kernel log, optionally only as far down as a level: dmesg [level]",
+        run: Action::Now(dmesg),
+    },
+    Command {
+        name: "echo",
+        help: "Prints its arguments",
+        run: Action::Now(|args| {
+            writeln!(global_writer::lock(), "{args}").ok();
+        }),
+    },
+    Command {
+        name: "cursor",
+        help: "Sets the shape of the cursor: cursor <underline|half|block|hidden>",
+        run: Action::Now(cursor),
+    },
+    Command {
+        name: "edit",
+        help: "Opens a file in the text editor: edit <path>",
+        run: Action::Task(edit),
+    },
+    Command {
+        name: "font",
+        help: "Loads a PSF font, or puts back the original one: font <path|reset>",
+        run: Action::Now(font),
+    },
+    Command {
+        name: "keymap",
+        help: "Shows or switches the keyboard layout: keymap [name]",
+        run: Action::Now(keymap),
+    },
+    Command {
+        name: "loglevel",
+        help: "Shows or sets the log level, for everything or one module: loglevel [level [module]]",
+        run: Action::Now(loglevel),
+    },
+    Command {
+        name: "repeat",
+        help: "Sets the key repeat delay and rate: repeat <delay ms> <chars per second>",
+        run: Action::Now(repeat),
+    },
+    #[cfg(feature = "framebuffer")]
+    Command {
+        name: "view",
+        help: "Shows a BMP image until a key is pressed: view <path>",
+        run: Action::Task(view),
+    },
+    Command {
+        name: "monitor",
+        help: "Shows a live task and heap monitor",
+        run: Action::Task(|_| Box::pin(monitor::run())),
+    },
+    Command {
+        name: "tasks",
+        help: "Lists every task and its statistics",
+        run: Action::Now(|_| print_tasks(&Executor::tasks())),
+    },
+    Command {
+        name: "top",
+        help: "Lists the tasks that used the most CPU time: top [count]",
+        run: Action::Now(top),
+    },
+    Command {
+        name: "shutdown",
+        help: "Powers off the machine",
+        run: Action::Now(|_| power::shutdown()),
+    },
+    Command {
+        name: "reboot",
+        help: "Restarts the machine",
+        run: Action::Now(|_| power::reboot()),
+    },
+];
+
+/// Prints the prompt for the next command.
+pub fn prompt() {
+    write!(global_writer::lock(), "{PROMPT}").ok();
+}
+
+/// Takes input focus and runs commands as they're typed.
+pub async fn run_shell() {
+    let mut input = input::focus();
+    // The line that is currently being typed
+    let mut line = String::new();
+
+    prompt();
+
+    while let Some(event) = input.recv().await {
+        let InputEvent::Key(key) = event else {
+            continue;
+        };
+
+        if let Some(line) = handle_key(&mut line, key) {
+            run(&line).await;
+            prompt();
+        }
+    }
+}
+
+/// Edits the current line. Returns the line once enter is pressed.
+pub fn handle_key(line: &mut String, event: KeyEvent) -> Option<String> {
+    let key = event.key?;
+
+    match key {
+        DecodedKey::Unicode('\n') => {
+            writeln!(global_writer::lock()).ok();
+            return Some(core::mem::take(line));
+        }
+        // Ctrl+C abandons the line
+        DecodedKey::Unicode('\u{3}') => {
+            line.clear();
+
+            writeln!(global_writer::lock(), "^C").ok();
+            prompt();
+        }
+        // Ctrl+L clears the screen, keeping the line
+        DecodedKey::Unicode('\u{c}') => {
+            global_writer::lock().clear_all();
+            prompt();
+            write!(global_writer::lock(), "{line}").ok();
+        }
+        DecodedKey::Unicode('\u{8}') => {
+            if line.pop().is_some() {
+                global_writer::lock().backspace();
+            }
+        }
+        DecodedKey::Unicode(character) if !character.is_control() => {
+            line.push(character);
+            write!(global_writer::lock(), "{character}").ok();
+        }
+        _ => {}
+    }
+
+    None
+}
+
+async fn run(line: &str) {
+    let line = line.trim();
+
+    if line.is_empty() {
+        return;
+    }
+
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(Command { run: Action::Now(command), .. }) => command(args.trim()),
+        Some(Command { run: Action::Task(command), .. }) => command(args.trim()).await,
+        None => {
+            writeln!(global_writer::lock(), "Unknown command `{name}`. Type `help` for a list of commands.").ok();
+        }
+    }
+}
+
+fn help(_: &str) {
+    let mut screen = global_writer::lock();
+
+    for command in COMMANDS {
+        writeln!(screen, "{:<10} {}", command.name, command.help).ok();
+    }
+}
+
+fn print_tasks(tasks: &[TaskInfo]) {
+    let mut screen = global_writer::lock();
+    let now = time::uptime();
+
+    writeln!(
+        screen,
+        "{:>4} {:>3} {:<11} {:<8} {:>7} {:>10} {:>9}  NAME",
+        "ID", "CPU", "PRIORITY", "STATE", "POLLS", "CPU TIME", "WOKEN"
+    ).ok();
+
+    for task in tasks {
+        let cpu = task.last_cpu.map_or("-".into(), |cpu| format!("{cpu}"));
+        let priority = format!("{:?}", task.priority);
+        let state = format!("{:?}", task.state);
+        let woken = task
+            .last_woken
+            .map_or("never".into(), |woken| format!("{:.1?}", now.saturating_sub(woken.since_boot())));
+
+        writeln!(
+            screen,
+            "{:>4} {:>3} {:<11} {:<8} {:>7} {:>10} {:>9}  {}",
+            task.id,
+            cpu,
+            priority,
+            state,
+            task.polls,
+            format!("{:.1?}", task.busy),
+            woken,
+            task.name.as_deref().unwrap_or("<unnamed>"),
+        ).ok();
+    }
+}
+
+fn top(args: &str) {
+    let count = match args {
+        "" => 10,
+        count => match count.parse() {
+            Ok(count) => count,
+            Err(_) => {
+                writeln!(global_writer::lock(), "Usage: top [count]").ok();
+                return;
+            }
+        },
+    };
+
+    let mut tasks = Executor::tasks();
+    tasks.sort_by_key(|task| Reverse(task.busy));
+    tasks.truncate(count);
+
+    print_tasks(&tasks);
+
+    // How much of the available CPU time the listed tasks used
+    let available = time::uptime().as_secs_f64() * smp::cpus_online() as f64;
+    let busy: f64 = tasks.iter().map(|task| task.busy.as_secs_f64()).sum();
+
+    if available > 0.0 {
+        writeln!(global_writer::lock(), "{:.2}% of CPU time since boot", busy / available * 100.0).ok();
+    }
+}
+
+fn cursor(args: &str) {
+    let shape = match args {
+        "underline" => Some(CursorShape::UNDERLINE),
+        "half" => Some(CursorShape::HALF_BLOCK),
+        "block" => Some(CursorShape::BLOCK),
+        "hidden" => None,
+        _ => {
+            writeln!(global_writer::lock(), "Usage: cursor <underline|half|block|hidden>").ok();
+            return;
+        }
+    };
+
+    global_writer::lock().set_cursor_shape(shape);
+}
+
+fn edit(args: &str) -> BoxFuture<'static, ()> {
+    if args.is_empty() {
+        writeln!(global_writer::lock(), "Usage: edit <path>").ok();
+        return Box::pin(async {});
+    }
+
+    Box::pin(Editor::open(Path::new(args)).run())
+}
+
+#[cfg(feature = "framebuffer")]
+fn view(args: &str) -> BoxFuture<'static, ()> {
+    use crate::framebuffer;
+    use crate::graphics::{Draw, Image, Rgb};
+
+    let image = match Image::load(&Path::new(args)) {
+        Ok(image) => image,
+        Err(error) => {
+            writeln!(global_writer::lock(), "Couldn't load `{args}`: {error}").ok();
+            return Box::pin(async {});
+        }
+    };
+
+    Box::pin(async move {
+        let mut input = input::focus();
+
+        let shown = framebuffer::with_framebuffer(|screen| {
+            let x = (screen.width() as isize - image.width() as isize) / 2;
+            let y = (screen.height() as isize - image.height() as isize) / 2;
+
+            screen.clear(Rgb::BLACK);
+            screen.blit(&image, x, y);
+        });
+
+        if shown.is_none() {
+            writeln!(global_writer::lock(), "Images can only be shown on the framebuffer console").ok();
+            return;
+        }
+
+        while let Some(event) = input.recv().await {
+            if matches!(event, InputEvent::Key(KeyEvent { key: Some(_), .. })) {
+                break;
+            }
+        }
+
+        framebuffer::redraw();
+    })
+}
+
+fn dmesg(args: &str) {
+    let level = match args {
+        "" => LevelFilter::Trace,
+        level => match level.parse() {
+            Ok(level) => level,
+            Err(_) => {
+                writeln!(global_writer::lock(), "Usage: dmesg [error|warn|info|debug|trace]").ok();
+                return;
+            }
+        },
+    };
+
+    // Collected first, since logging while the screen is locked would wait on it
+    let records = logger::records();
+    let mut screen = global_writer::lock();
+
+    for record in records.iter().filter(|record| record.level <= level) {
+        screen.write_colourful(&record.to_colour_text());
+        screen.write_string("\n");
+    }
+}
+
+fn loglevel(args: &str) {
+    let mut args = args.split_whitespace();
+
+    let Some(level) = args.next() else {
+        let mut screen = global_writer::lock();
+
+        writeln!(screen, "Default level: {}", logger::level("")).ok();
+        for (name, level) in logger::sinks() {
+            writeln!(screen, "The {name} sink shows {level} and more severe").ok();
+        }
+        return;
+    };
+
+    match level.parse() {
+        Ok(level) => logger::set_level(args.next(), level),
+        Err(_) => {
+            writeln!(global_writer::lock(), "Usage: loglevel [off|error|warn|info|debug|trace [module]]").ok();
+        }
+    }
+}
+
+fn font(args: &str) {
+    match args {
+        "" => {
+            writeln!(global_writer::lock(), "Usage: font <path|reset>").ok();
+        }
+        "reset" => font::reset(),
+        path => {
+            // Loading a font holds the screen, so the writer can't be locked until it's done
+            if let Err(error) = font::load(&Path::new(path)) {
+                writeln!(global_writer::lock(), "Couldn't load `{path}`: {error}").ok();
+            }
+        }
+    }
+}
+
+fn keymap(args: &str) {
+    let mut screen = global_writer::lock();
+
+    if args.is_empty() {
+        writeln!(screen, "Current layout: {}", keyboard::layout().name()).ok();
+        writeln!(screen, "Built in: {}", Layout::BUILT_IN.join(", ")).ok();
+        writeln!(screen, "Keymap files: {}", Layout::keymap_files().join(", ")).ok();
+        return;
+    }
+
+    match Layout::load(args) {
+        Ok(layout) => {
+            writeln!(screen, "Switched to the `{}` layout", layout.name()).ok();
+            keyboard::set_layout(layout);
+        }
+        Err(err) => {
+            writeln!(screen, "Can't load layout `{args}`: {err}").ok();
+        }
+    }
+}
+
+fn repeat(args: &str) {
+    let mut args = args.split_whitespace().map(str::parse::<u32>);
+
+    let (Some(Ok(delay)), Some(Ok(rate)), None) = (args.next(), args.next(), args.next()) else {
+        writeln!(global_writer::lock(), "Usage: repeat <delay ms> <chars per second>").ok();
+        return;
+    };
+
+    // Errors are reported by the driver
+    ps2::set_typematic(core::time::Duration::from_millis(delay as u64), rate).ok();
+}
@@ -0,0 +1,137 @@
+use core::arch::x86_64::_rdtsc;
+use core::ops::Sub;
+use core::ptr;
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::acpi;
+use crate::interrupts;
+use crate::memory;
+
+/// How many timestamp counter cycles pass per second
+static TSC_HZ: OnceCell<u64> = OnceCell::uninit();
+
+/// A point in time, as read from the CPU's timestamp counter. Cheap enough to take on every
+/// task poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(unsafe { _rdtsc() })
+    }
+
+    pub fn from_cycles(cycles: u64) -> Self {
+        Instant(cycles)
+    }
+
+    /// The raw timestamp counter value, for storing in an atomic
+    pub fn cycles(&self) -> u64 {
+        self.0
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - *self
+    }
+
+    /// The time since boot
+    pub fn since_boot(&self) -> Duration {
+        cycles_to_duration(self.0)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        cycles_to_duration(self.0.saturating_sub(earlier.0))
+    }
+}
+
+/// Converts timestamp counter cycles to time. Zero until `init` has run.
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    let Ok(&hz) = TSC_HZ.try_get() else {
+        return Duration::ZERO;
+    };
+
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / hz as u128) as u64)
+}
+
+/// The time since boot
+pub fn uptime() -> Duration {
+    Instant::now().since_boot()
+}
+
+/// Measures the timestamp counter's frequency against the HPET, if the firmware has one, or
+/// else against the timer interrupt. Needs interrupts to be enabled.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let hpet_hz = match acpi::hpet() {
+        Some(hpet) => tsc_hz_from_hpet(memory::map_mmio(hpet.base_address, 1024, mapper, frame_allocator)?),
+        None => None,
+    };
+
+    let hz = hpet_hz.unwrap_or_else(tsc_hz_from_ticks);
+    TSC_HZ.init_once(|| hz);
+
+    Ok(())
+}
+
+fn tsc_hz_from_ticks() -> u64 {
+    const CALIBRATION_TICKS: u64 = 10;
+
+    // Line up with the start of a tick
+    interrupts::wait_ticks(0);
+
+    let start = Instant::now();
+    interrupts::wait_ticks(CALIBRATION_TICKS - 1);
+    let cycles = Instant::now().0 - start.0;
+
+    cycles * interrupts::TIMER_HZ as u64 / CALIBRATION_TICKS
+}
+
+/// Times 10ms on the HPET's main counter, starting it if the firmware hasn't. Returns `None` if
+/// the HPET reports a tick length that's out of spec.
+fn tsc_hz_from_hpet(base: VirtAddr) -> Option<u64> {
+    const CAPABILITIES: u64 = 0x000;
+    const CONFIGURATION: u64 = 0x010;
+    const MAIN_COUNTER: u64 = 0x0f0;
+    /// In `CAPABILITIES`; otherwise the main counter is 32 bits and wraps much sooner
+    const COUNTER_64_BIT: u64 = 1 << 13;
+    /// In `CONFIGURATION`
+    const ENABLE: u64 = 1;
+    const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+    /// The longest tick the specification allows, 100ns
+    const MAX_PERIOD: u64 = 100_000_000;
+
+    let read = |reg: u64| unsafe { ptr::read_volatile((base + reg).as_ptr::<u64>()) };
+
+    let capabilities = read(CAPABILITIES);
+    // The length of a tick, in femtoseconds
+    let period = capabilities >> 32;
+    let counter_mask = if capabilities & COUNTER_64_BIT != 0 { u64::MAX } else { u32::MAX as u64 };
+
+    if period == 0 || period > MAX_PERIOD {
+        return None;
+    }
+
+    unsafe { ptr::write_volatile((base + CONFIGURATION).as_mut_ptr::<u64>(), read(CONFIGURATION) | ENABLE) };
+
+    let ticks = FEMTOSECONDS_PER_SECOND / 100 / period;
+    let (start_count, start) = (read(MAIN_COUNTER), Instant::now());
+
+    let mut elapsed = 0;
+    while elapsed < ticks {
+        core::hint::spin_loop();
+        elapsed = read(MAIN_COUNTER).wrapping_sub(start_count) & counter_mask;
+    }
+
+    let cycles = Instant::now().0 - start.0;
+
+    Some((cycles as u128 * FEMTOSECONDS_PER_SECOND as u128 / (elapsed * period) as u128) as u64)
+}
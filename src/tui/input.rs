@@ -0,0 +1,90 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{theme, Canvas, Widget};
+use crate::font;
+use crate::task::keyboard::KeyEvent;
+
+/// A single line of editable text
+pub struct TextInput {
+    text: Vec<char>,
+    /// The index of the character that the cursor is in front of
+    cursor: usize,
+    focused: bool,
+}
+
+impl TextInput {
+    pub fn new() -> TextInput {
+        TextInput {
+            text: Vec::new(),
+            cursor: 0,
+            focused: false,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+}
+
+impl Widget for TextInput {
+    fn draw(&self, canvas: &mut Canvas) {
+        let colour = if self.focused { theme::FOCUSED_INPUT } else { theme::INPUT };
+        let width = canvas.width();
+
+        if width == 0 {
+            return;
+        }
+
+        // Keep the cursor in view, with room for it after the last character
+        let start = (self.cursor + 1).saturating_sub(width);
+        let visible: String = self.text.iter().skip(start).take(width).collect();
+
+        canvas.fill(b' ', colour);
+        canvas.text(0, 0, &visible, colour);
+
+        if self.focused {
+            let under = self.text.get(self.cursor).map_or(b' ', |&character| font::glyph(character));
+
+            canvas.put(self.cursor - start, 0, under, theme::INPUT);
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        match key.key {
+            Some(DecodedKey::Unicode('\u{8}')) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            Some(DecodedKey::Unicode('\u{7f}')) => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            Some(DecodedKey::Unicode(character)) if !character.is_control() => {
+                self.text.insert(self.cursor, character);
+                self.cursor += 1;
+            }
+            Some(DecodedKey::RawKey(KeyCode::ArrowLeft)) => self.cursor = self.cursor.saturating_sub(1),
+            Some(DecodedKey::RawKey(KeyCode::ArrowRight)) => self.cursor = (self.cursor + 1).min(self.text.len()),
+            Some(DecodedKey::RawKey(KeyCode::Home)) => self.cursor = 0,
+            Some(DecodedKey::RawKey(KeyCode::End)) => self.cursor = self.text.len(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn focus_next(&mut self) -> bool {
+        self.focused = !self.focused;
+        self.focused
+    }
+
+    fn has_focus(&self) -> bool {
+        self.focused
+    }
+}
@@ -0,0 +1,36 @@
+use alloc::string::String;
+
+use super::{theme, Canvas, Widget};
+use crate::vga_buffer::ColourCode;
+
+/// A line of text that can't be focused
+pub struct Label {
+    text: String,
+    colour: ColourCode,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Label {
+        Label {
+            text: text.into(),
+            colour: theme::WINDOW,
+        }
+    }
+
+    pub fn with_colour(mut self, colour: ColourCode) -> Label {
+        self.colour = colour;
+        self
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text.clear();
+        self.text.push_str(text);
+    }
+}
+
+impl Widget for Label {
+    fn draw(&self, canvas: &mut Canvas) {
+        canvas.fill(b' ', self.colour);
+        canvas.text(0, 0, &self.text, self.colour);
+    }
+}
@@ -0,0 +1,184 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::{focus_next_child, Canvas, Widget};
+use crate::task::keyboard::KeyEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Top to bottom
+    Vertical,
+    /// Left to right
+    Horizontal,
+}
+
+/// How much space a widget gets along the direction it's laid out in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    /// Exactly this many rows or columns, or whatever is left if there isn't enough
+    Fixed(usize),
+    /// A share of the space left over after the fixed widgets
+    Fill,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// The same rectangle with `margin` taken off every side
+    pub fn inset(self, margin: usize) -> Rect {
+        Rect {
+            x: self.x + margin,
+            y: self.y + margin,
+            width: self.width.saturating_sub(margin * 2),
+            height: self.height.saturating_sub(margin * 2),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The part that's in both rectangles, which is empty if they don't overlap
+    pub fn intersection(self, other: Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    /// The smallest rectangle that covers both. Empty rectangles are left out.
+    pub fn union(self, other: Rect) -> Rect {
+        if self.is_empty() {
+            return other;
+        } else if other.is_empty() {
+            return self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Splits the rectangle into one piece per constraint. Fixed pieces are sized first, then
+    /// whatever is left is shared between the `Fill` pieces, with any remainder going to the
+    /// first ones.
+    pub fn split(self, direction: Direction, constraints: &[Constraint]) -> Vec<Rect> {
+        let total = match direction {
+            Direction::Vertical => self.height,
+            Direction::Horizontal => self.width,
+        };
+
+        let fixed: usize = constraints
+            .iter()
+            .map(|constraint| match constraint {
+                Constraint::Fixed(size) => *size,
+                Constraint::Fill => 0,
+            })
+            .sum();
+        let fills = constraints.iter().filter(|&&constraint| constraint == Constraint::Fill).count();
+        let spare = total.saturating_sub(fixed);
+
+        let mut offset = 0;
+        let mut fill_index = 0;
+
+        constraints
+            .iter()
+            .map(|constraint| {
+                let wanted = match constraint {
+                    Constraint::Fixed(size) => *size,
+                    Constraint::Fill => {
+                        fill_index += 1;
+                        spare / fills + usize::from(fill_index <= spare % fills)
+                    }
+                };
+                let size = wanted.min(total - offset);
+                let start = offset;
+                offset += size;
+
+                match direction {
+                    Direction::Vertical => Rect::new(self.x, self.y + start, self.width, size),
+                    Direction::Horizontal => Rect::new(self.x + start, self.y, size, self.height),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lays its children out in a row or a column.
+pub struct Stack {
+    direction: Direction,
+    children: Vec<(Constraint, Box<dyn Widget>)>,
+    areas: Vec<Rect>,
+    focused: Option<usize>,
+}
+
+impl Stack {
+    pub fn new(direction: Direction) -> Stack {
+        Stack {
+            direction,
+            children: Vec::new(),
+            areas: Vec::new(),
+            focused: None,
+        }
+    }
+
+    /// Adds a child after the others.
+    pub fn with(mut self, constraint: Constraint, child: impl Widget) -> Stack {
+        self.children.push((constraint, Box::new(child)));
+        self
+    }
+
+    /// The child at `index`, if it's a `T`
+    pub fn child_mut<T: Widget>(&mut self, index: usize) -> Option<&mut T> {
+        let child: &mut dyn core::any::Any = &mut *self.children.get_mut(index)?.1;
+        child.downcast_mut()
+    }
+}
+
+impl Widget for Stack {
+    fn layout(&mut self, area: Rect) {
+        let constraints: Vec<_> = self.children.iter().map(|(constraint, _)| *constraint).collect();
+        self.areas = Rect::new(0, 0, area.width, area.height).split(self.direction, &constraints);
+
+        for ((_, child), area) in self.children.iter_mut().zip(&self.areas) {
+            child.layout(*area);
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        for ((_, child), area) in self.children.iter().zip(&self.areas) {
+            child.draw(&mut canvas.sub(*area));
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        match self.focused {
+            Some(index) => self.children[index].1.handle_key(key),
+            None => false,
+        }
+    }
+
+    fn focus_next(&mut self) -> bool {
+        let mut children: Vec<&mut dyn Widget> = self.children.iter_mut().map(|(_, child)| &mut **child).collect();
+        focus_next_child(&mut children, &mut self.focused)
+    }
+
+    fn has_focus(&self) -> bool {
+        self.focused.is_some()
+    }
+}
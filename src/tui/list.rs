@@ -0,0 +1,98 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{theme, Canvas, Rect, Widget};
+use crate::task::keyboard::KeyEvent;
+
+/// A scrolling list with one selected row
+pub struct ListView {
+    items: Vec<String>,
+    selected: usize,
+    /// The first item on screen
+    offset: usize,
+    /// The number of rows from the last layout pass
+    height: usize,
+    focused: bool,
+}
+
+impl ListView {
+    pub fn new() -> ListView {
+        ListView {
+            items: Vec::new(),
+            selected: 0,
+            offset: 0,
+            height: 0,
+            focused: false,
+        }
+    }
+
+    /// Replaces the items, keeping the selection where it is if it's still in range.
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        self.select(self.selected);
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        (self.selected < self.items.len()).then_some(self.selected)
+    }
+
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+        self.scroll();
+    }
+
+    /// Scrolls so that the selected item is on screen.
+    fn scroll(&mut self) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.height > 0 && self.selected >= self.offset + self.height {
+            self.offset = self.selected + 1 - self.height;
+        }
+    }
+}
+
+impl Widget for ListView {
+    fn layout(&mut self, area: Rect) {
+        self.height = area.height;
+        self.scroll();
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        canvas.fill(b' ', theme::WINDOW);
+
+        for (row, (index, item)) in self.items.iter().enumerate().skip(self.offset).take(canvas.height()).enumerate() {
+            let colour = if index == self.selected && self.focused { theme::SELECTED } else { theme::WINDOW };
+
+            let mut line = canvas.sub(Rect::new(0, row, canvas.width(), 1));
+            line.fill(b' ', colour);
+            line.text(0, 0, item, colour);
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        let page = self.height.max(1);
+
+        match key.key {
+            Some(DecodedKey::RawKey(KeyCode::ArrowUp)) => self.select(self.selected.saturating_sub(1)),
+            Some(DecodedKey::RawKey(KeyCode::ArrowDown)) => self.select(self.selected + 1),
+            Some(DecodedKey::RawKey(KeyCode::PageUp)) => self.select(self.selected.saturating_sub(page)),
+            Some(DecodedKey::RawKey(KeyCode::PageDown)) => self.select(self.selected + page),
+            Some(DecodedKey::RawKey(KeyCode::Home)) => self.select(0),
+            Some(DecodedKey::RawKey(KeyCode::End)) => self.select(usize::MAX),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn focus_next(&mut self) -> bool {
+        self.focused = !self.focused;
+        self.focused
+    }
+
+    fn has_focus(&self) -> bool {
+        self.focused
+    }
+}
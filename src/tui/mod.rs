@@ -0,0 +1,207 @@
+//! A small retained-mode widget toolkit for VGA text mode.
+//!
+//! Widgets are kept between frames and own their state (the text in an input, the selected
+//! row of a list). Each frame goes through two passes: `layout` hands every widget the
+//! rectangle it gets, then `draw` paints it onto a `Canvas`, which clips to that rectangle and
+//! writes through `BufWrite`.
+//!
+//! Focus moves with Tab. Containers remember which child has focus and pass keys to it; a
+//! widget that doesn't use a key returns `false`, so its parents can.
+
+pub mod input;
+pub mod label;
+pub mod layout;
+pub mod list;
+pub mod progress;
+pub mod status;
+pub mod window;
+
+use core::any::Any;
+
+use pc_keyboard::DecodedKey;
+
+use crate::font;
+use crate::task::keyboard::KeyEvent;
+use crate::vga_buffer::{global_writer, screen_size, BufWrite, ColourCode};
+
+#[allow(unused_imports)]
+pub use self::{
+    input::TextInput,
+    label::Label,
+    layout::{Constraint, Direction, Rect, Stack},
+    list::ListView,
+    progress::ProgressBar,
+    status::StatusBar,
+    window::Window,
+};
+
+/// The colours that widgets are drawn in
+pub mod theme {
+    use crate::vga_buffer::ColourCode;
+
+    /// White on blue
+    pub const WINDOW: ColourCode = ColourCode(0x1f);
+    /// Yellow on blue, for the border of the window with focus
+    pub const FOCUSED_BORDER: ColourCode = ColourCode(0x1e);
+    /// Black on light grey
+    pub const INPUT: ColourCode = ColourCode(0x70);
+    /// White on cyan
+    pub const FOCUSED_INPUT: ColourCode = ColourCode(0x3f);
+    /// Black on cyan
+    pub const SELECTED: ColourCode = ColourCode(0x30);
+    /// Black on light grey
+    pub const STATUS_BAR: ColourCode = ColourCode(0x70);
+}
+
+/// Something that can be laid out and drawn, and that might take focus.
+pub trait Widget: Any + Send {
+    /// Gives the widget the area it'll be drawn in, relative to its parent. Containers lay out
+    /// their children here.
+    fn layout(&mut self, _area: Rect) {}
+
+    /// Draws the widget. The canvas covers the area from the last layout pass.
+    fn draw(&self, canvas: &mut Canvas);
+
+    /// Handles a key. Returns `false` if the widget didn't use it.
+    fn handle_key(&mut self, _key: &KeyEvent) -> bool {
+        false
+    }
+
+    /// Moves focus forwards. A widget that can take focus takes it if it doesn't have it, and
+    /// gives it up (returning `false`) if it did, so that its parent can move on to the next one.
+    fn focus_next(&mut self) -> bool {
+        false
+    }
+
+    fn has_focus(&self) -> bool {
+        false
+    }
+}
+
+/// Moves focus to the next child that takes it, for widgets with several children. Returns
+/// `false`, with nothing focused, once focus has gone past the last child.
+pub fn focus_next_child(children: &mut [&mut dyn Widget], focused: &mut Option<usize>) -> bool {
+    let start = match *focused {
+        Some(index) if children[index].focus_next() => return true,
+        Some(index) => index + 1,
+        None => 0,
+    };
+
+    *focused = (start..children.len()).find(|&index| children[index].focus_next());
+
+    focused.is_some()
+}
+
+/// Part of a text buffer that widgets draw onto. Coordinates are relative to the top left of
+/// the canvas, and anything outside of it is clipped.
+pub struct Canvas<'a> {
+    buffer: &'a mut dyn BufWrite,
+    area: Rect,
+}
+
+impl<'a> Canvas<'a> {
+    /// A canvas covering `area` of `buffer`. `area` must fit inside the buffer.
+    pub fn new(buffer: &'a mut dyn BufWrite, area: Rect) -> Canvas<'a> {
+        Canvas { buffer, area }
+    }
+
+    pub fn width(&self) -> usize {
+        self.area.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.area.height
+    }
+
+    /// Writes a character, in code page 437.
+    pub fn put(&mut self, x: usize, y: usize, byte: u8, colour: ColourCode) {
+        if x < self.area.width && y < self.area.height {
+            self.buffer.write_char(colour, byte, self.area.y + y, self.area.x + x).ok();
+        }
+    }
+
+    /// Writes text on one row, cut off at the edge. Returns the number of columns written.
+    pub fn text(&mut self, x: usize, y: usize, text: &str, colour: ColourCode) -> usize {
+        let mut written = 0;
+
+        for (col, character) in (x..self.area.width).zip(text.chars()) {
+            self.put(col, y, font::glyph(character), colour);
+            written += 1;
+        }
+
+        written
+    }
+
+    /// Fills the whole canvas with one character.
+    pub fn fill(&mut self, byte: u8, colour: ColourCode) {
+        for y in 0..self.area.height {
+            for x in 0..self.area.width {
+                self.put(x, y, byte, colour);
+            }
+        }
+    }
+
+    /// A canvas covering part of this one. `area` is relative to this canvas, and is clipped
+    /// to it.
+    pub fn sub(&mut self, area: Rect) -> Canvas<'_> {
+        let x = area.x.min(self.area.width);
+        let y = area.y.min(self.area.height);
+
+        Canvas {
+            buffer: &mut *self.buffer,
+            area: Rect {
+                x: self.area.x + x,
+                y: self.area.y + y,
+                width: area.width.min(self.area.width - x),
+                height: area.height.min(self.area.height - y),
+            },
+        }
+    }
+}
+
+/// A widget tree that fills the screen. Focus starts on the first widget that takes it.
+pub struct Screen<W: Widget> {
+    root: W,
+}
+
+impl<W: Widget> Screen<W> {
+    pub fn new(root: W) -> Screen<W> {
+        let mut screen = Screen { root };
+        screen.root.focus_next();
+
+        screen
+    }
+
+    pub fn root_mut(&mut self) -> &mut W {
+        &mut self.root
+    }
+
+    /// Lays out and draws the whole tree onto the screen.
+    pub fn draw(&mut self) {
+        let (columns, rows) = screen_size();
+        let area = Rect::new(0, 0, columns, rows);
+        self.root.layout(area);
+
+        let mut screen = global_writer::lock();
+        self.root.draw(&mut Canvas::new(&mut screen.buffer, area));
+        screen.draw_mouse_cursor();
+    }
+
+    /// Passes a key to the widget with focus. Tab moves focus on, unless that widget uses it.
+    /// Returns `false` if nothing used the key.
+    pub fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        if self.root.handle_key(key) {
+            return true;
+        }
+
+        if key.key == Some(DecodedKey::Unicode('\t')) {
+            // Going past the last widget leaves nothing focused, so start again from the first
+            if !self.root.focus_next() {
+                self.root.focus_next();
+            }
+            return true;
+        }
+
+        false
+    }
+}
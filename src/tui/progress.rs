@@ -0,0 +1,49 @@
+use alloc::format;
+use alloc::string::String;
+
+use super::{theme, Canvas, Widget};
+
+const FULL: u8 = 0xdb;
+const EMPTY: u8 = 0xb0;
+
+/// A bar that fills up from the left, with a label and a percentage after it
+pub struct ProgressBar {
+    label: String,
+    done: u64,
+    total: u64,
+}
+
+impl ProgressBar {
+    pub fn new(label: &str) -> ProgressBar {
+        ProgressBar {
+            label: label.into(),
+            done: 0,
+            total: 1,
+        }
+    }
+
+    pub fn set_progress(&mut self, done: u64, total: u64) {
+        self.total = total.max(1);
+        self.done = done.min(self.total);
+    }
+}
+
+impl Widget for ProgressBar {
+    fn draw(&self, canvas: &mut Canvas) {
+        canvas.fill(b' ', theme::WINDOW);
+
+        let percent = format!("{:>3}%", self.done * 100 / self.total);
+        let label_width = canvas.text(0, 0, &self.label, theme::WINDOW);
+
+        // The label, a space, the bar, a space, then the percentage
+        let bar_start = label_width + 1;
+        let bar_width = canvas.width().saturating_sub(bar_start + 1 + percent.len());
+        let filled = (bar_width as u64 * self.done / self.total) as usize;
+
+        for x in 0..bar_width {
+            canvas.put(bar_start + x, 0, if x < filled { FULL } else { EMPTY }, theme::WINDOW);
+        }
+
+        canvas.text(bar_start + bar_width + 1, 0, &percent, theme::WINDOW);
+    }
+}
@@ -0,0 +1,40 @@
+use alloc::string::String;
+
+use super::{theme, Canvas, Widget};
+
+/// A bar with text at both ends, usually along the bottom of the screen
+pub struct StatusBar {
+    left: String,
+    right: String,
+}
+
+impl StatusBar {
+    pub fn new() -> StatusBar {
+        StatusBar {
+            left: String::new(),
+            right: String::new(),
+        }
+    }
+
+    pub fn set_left(&mut self, text: &str) {
+        self.left.clear();
+        self.left.push_str(text);
+    }
+
+    pub fn set_right(&mut self, text: &str) {
+        self.right.clear();
+        self.right.push_str(text);
+    }
+}
+
+impl Widget for StatusBar {
+    fn draw(&self, canvas: &mut Canvas) {
+        canvas.fill(b' ', theme::STATUS_BAR);
+
+        // The right-hand text wins if they overlap
+        canvas.text(1, 0, &self.left, theme::STATUS_BAR);
+
+        let right_width = self.right.chars().count() + 1;
+        canvas.text(canvas.width().saturating_sub(right_width), 0, &self.right, theme::STATUS_BAR);
+    }
+}
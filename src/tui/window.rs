@@ -0,0 +1,91 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+
+use super::{theme, Canvas, Rect, Widget};
+use crate::task::keyboard::KeyEvent;
+
+/// Corners, then the horizontal and vertical edges, in code page 437
+const SINGLE_BORDER: [u8; 6] = [0xda, 0xbf, 0xc0, 0xd9, 0xc4, 0xb3];
+const DOUBLE_BORDER: [u8; 6] = [0xc9, 0xbb, 0xc8, 0xbc, 0xcd, 0xba];
+
+/// A bordered box with a title. The border is doubled while something inside has focus.
+pub struct Window {
+    title: String,
+    child: Box<dyn Widget>,
+}
+
+impl Window {
+    pub fn new(title: &str, child: impl Widget) -> Window {
+        Window {
+            title: title.into(),
+            child: Box::new(child),
+        }
+    }
+
+    pub fn child_mut<T: Widget>(&mut self) -> Option<&mut T> {
+        let child: &mut dyn core::any::Any = &mut *self.child;
+        child.downcast_mut()
+    }
+
+    /// Clears the canvas and draws a border around it, with the title in the top edge. Returns
+    /// the area inside the border.
+    pub fn draw_frame(canvas: &mut Canvas, title: &str, focused: bool) -> Rect {
+        let (width, height) = (canvas.width(), canvas.height());
+
+        if width < 2 || height < 2 {
+            return Rect::default();
+        }
+
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] =
+            if focused { DOUBLE_BORDER } else { SINGLE_BORDER };
+        let colour = if focused { theme::FOCUSED_BORDER } else { theme::WINDOW };
+
+        canvas.fill(b' ', theme::WINDOW);
+
+        for x in 1..width - 1 {
+            canvas.put(x, 0, horizontal, colour);
+            canvas.put(x, height - 1, horizontal, colour);
+        }
+        for y in 1..height - 1 {
+            canvas.put(0, y, vertical, colour);
+            canvas.put(width - 1, y, vertical, colour);
+        }
+
+        canvas.put(0, 0, top_left, colour);
+        canvas.put(width - 1, 0, top_right, colour);
+        canvas.put(0, height - 1, bottom_left, colour);
+        canvas.put(width - 1, height - 1, bottom_right, colour);
+
+        if !title.is_empty() && width > 4 {
+            let mut title_canvas = canvas.sub(Rect::new(2, 0, width - 4, 1));
+            let written = title_canvas.text(1, 0, title, colour);
+            title_canvas.put(0, 0, b' ', colour);
+            title_canvas.put(written + 1, 0, b' ', colour);
+        }
+
+        Rect::new(0, 0, width, height).inset(1)
+    }
+}
+
+impl Widget for Window {
+    fn layout(&mut self, area: Rect) {
+        self.child.layout(Rect::new(0, 0, area.width, area.height).inset(1));
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        let inner = Window::draw_frame(canvas, &self.title, self.child.has_focus());
+        self.child.draw(&mut canvas.sub(inner));
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        self.child.handle_key(key)
+    }
+
+    fn focus_next(&mut self) -> bool {
+        self.child.focus_next()
+    }
+
+    fn has_focus(&self) -> bool {
+        self.child.has_focus()
+    }
+}
@@ -4,27 +4,51 @@ use core::{
 };
 
 use alloc::{
-    string::{String, ToString},
+    string::String,
     vec::{Vec},
 };
-use lazy_static::lazy_static;
-use spin::{Mutex, MutexGuard};
+use spin::MutexGuard;
 use volatile::Volatile;
 
-/// The width of the VGA buffer
-const BUFFER_WIDTH: usize = 80;
-/// The height of the VGA buffer
-const BUFFER_HEIGHT: usize = 25;
+use crate::font;
+
+/// The width of the VGA text buffer
+pub const TEXT_WIDTH: usize = 80;
+/// The height of the VGA text buffer
+pub const TEXT_HEIGHT: usize = 25;
+
+/// The width of the screen, in characters
+#[cfg(not(feature = "framebuffer"))]
+pub const BUFFER_WIDTH: usize = TEXT_WIDTH;
+/// The height of the screen, in characters
+#[cfg(not(feature = "framebuffer"))]
+pub const BUFFER_HEIGHT: usize = TEXT_HEIGHT;
+
+/// The width of the screen, in characters
+#[cfg(feature = "framebuffer")]
+pub const BUFFER_WIDTH: usize = crate::framebuffer::COLUMNS;
+/// The height of the screen, in characters
+#[cfg(feature = "framebuffer")]
+pub const BUFFER_HEIGHT: usize = crate::framebuffer::ROWS;
+
+/// The columns and rows that fit on screen: the framebuffer console's once it's running, or
+/// else the text buffer's. Terminals are stored at `BUFFER_WIDTH` by `BUFFER_HEIGHT`, but only
+/// use this much of it.
+pub fn screen_size() -> (usize, usize) {
+    #[cfg(feature = "framebuffer")]
+    if crate::framebuffer::is_active() {
+        return (BUFFER_WIDTH, BUFFER_HEIGHT);
+    }
+
+    (TEXT_WIDTH, TEXT_HEIGHT)
+}
 
-lazy_static! {
-    /// The global WRITER that is initialized on OS load
-    pub static ref WRITER: Mutex<Writer<BUFFER_WIDTH, BUFFER_HEIGHT, &'static mut Buffer<BUFFER_WIDTH, BUFFER_HEIGHT, Volatile<ScreenChar>>>> = Mutex::new(Writer {
-        column_position: ScreenPosition(0),
-        row_position: ScreenPosition(0),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer<BUFFER_WIDTH, BUFFER_HEIGHT, Volatile<ScreenChar>>) },
-        colour_code: ColourCode::default(),
-        lock_colour: false
-    });
+/// The VGA text buffer itself. Only the active virtual terminal writes to it (see `vt`).
+///
+/// # Safety
+/// The caller has to make sure that nothing else is writing to the screen at the same time.
+pub unsafe fn hardware_buffer() -> &'static mut Buffer<TEXT_WIDTH, TEXT_HEIGHT, Volatile<ScreenChar>> {
+    &mut *(0xb8000 as *mut Buffer<TEXT_WIDTH, TEXT_HEIGHT, Volatile<ScreenChar>>)
 }
 
 /// Colour codes for VGA text mode display
@@ -57,10 +81,13 @@ pub enum Colour {
 #[repr(transparent)]
 pub struct ColourCode(pub u8);
 
-/// ColourText is a String that has a VGA text mode colour code attached to it.
-/// It implements Display so that you may put it in a println! or format! statement.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ColourText(pub u8, pub String);
+/// ColourText is text in one or more VGA text mode colours. The colours are kept next to the
+/// text rather than in it, so displaying a ColourText (in a format! statement, or over serial)
+/// gives just the text; pass it to `Writer::write_colourful` to show it in colour.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ColourText {
+    spans: Vec<(ColourCode, String)>,
+}
 
 /// An abstraction for the on-screen characters.
 /// Contains a colour code and a character code.
@@ -87,9 +114,6 @@ pub struct ScreenPosition<const MAX: usize>(pub usize);
 
 /// This trait is used in the Writer struct to help with printing.
 pub trait BufWrite {
-    /// A character (or a reference to a character) that will be written to
-    type Character: BorrowMut<Volatile<ScreenChar>>;
-    
     /// Writes a character at a specified position
     fn write_char(&mut self,
         colour_code: ColourCode, 
@@ -105,18 +129,59 @@ pub trait BufWrite {
         colour: ColourCode
     );
 
-    /// Returns a mutable reference to all the characters in the buffer.
-    fn char_buf(&mut self) -> Vec<Vec<&mut Self::Character>>;
+    /// Reads back a character, or returns `None` if the position is out of bounds.
+    fn read_char(&mut self, row: usize, col: usize) -> Option<ScreenChar>;
+
+    /// The columns and rows in use, if that's fewer than the writer's `X` and `Y`
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Moves the blinking cursor. Buffers that aren't on screen can ignore this.
+    fn move_cursor(&mut self, _row: usize, _col: usize) {}
+
+    /// The shape of the blinking cursor, or `None` if it's hidden
+    fn cursor_shape(&self) -> Option<CursorShape> {
+        None
+    }
+
+    /// Shows the blinking cursor in the given shape, or hides it.
+    fn set_cursor_shape(&mut self, _shape: Option<CursorShape>) {}
+
+    /// Moves every row up by one, dropping the top row and filling the bottom one with `blank`.
+    /// Goes through `read_char` and `write_char`, so buffers that draw as they're written keep
+    /// the screen up to date.
+    fn scroll_up(&mut self, blank: ScreenChar) {
+        let mut row = 1;
+
+        while self.read_char(row, 0).is_some() {
+            let mut col = 0;
+
+            while let Some(character) = self.read_char(row, col) {
+                self.write_char(character.colour_code(), character.ascii_character(), row - 1, col).ok();
+                col += 1;
+            }
+
+            row += 1;
+        }
+
+        let mut col = 0;
+
+        while self.read_char(row - 1, col).is_some() {
+            self.write_char(blank.colour_code(), blank.ascii_character(), row - 1, col).ok();
+            col += 1;
+        }
+    }
 }
 
 impl<const X: usize, const Y: usize, T: BorrowMut<Volatile<ScreenChar>>> BufWrite for Buffer<X, Y, T> {
-    type Character = T;
-
     fn write_char(&mut self, colour_code: ColourCode, byte: u8, row: usize, col: usize) -> fmt::Result {
         match byte {
             b'\n' => Err(fmt::Error),
             byte => {
-                self.chars[row][col].borrow_mut().write(ScreenChar {
+                let character = self.chars.get_mut(row).and_then(|row| row.get_mut(col)).ok_or(fmt::Error)?;
+
+                character.borrow_mut().write(ScreenChar {
                     ascii_character: byte,
                     colour_code: colour_code.into(),
                 });
@@ -137,31 +202,19 @@ impl<const X: usize, const Y: usize, T: BorrowMut<Volatile<ScreenChar>>> BufWrit
         }
     }
 
-    fn char_buf(&mut self) -> Vec<Vec<&mut T>> {
-        let mut buf_ref: Vec<Vec<&mut T>> = vec![];
-
-        for row in self.chars.iter_mut() {
-            let mut row_ref = vec![];
-
-            for character_ref in row {
-                row_ref.push(character_ref)
-            }
-
-            buf_ref.push(row_ref)
-        }
-
-        buf_ref
+    fn read_char(&mut self, row: usize, col: usize) -> Option<ScreenChar> {
+        Some(self.chars.get(row)?.get(col)?.borrow().read())
     }
 }
 
 impl<const X: usize, const Y: usize, T: BorrowMut<Volatile<ScreenChar>>> BufWrite for &mut Buffer<X, Y, T> {
-    type Character = T;
-
     fn write_char(&mut self, colour_code: ColourCode, byte: u8, row: usize, col: usize) -> fmt::Result {
         match byte {
             b'\n' => Err(fmt::Error),
             byte => {
-                self.chars[row][col].borrow_mut().write(ScreenChar {
+                let character = self.chars.get_mut(row).and_then(|row| row.get_mut(col)).ok_or(fmt::Error)?;
+
+                character.borrow_mut().write(ScreenChar {
                     ascii_character: byte,
                     colour_code: colour_code.into(),
                 });
@@ -182,20 +235,8 @@ impl<const X: usize, const Y: usize, T: BorrowMut<Volatile<ScreenChar>>> BufWrit
         }
     }
 
-    fn char_buf(&mut self) -> Vec<Vec<&mut T>> {
-        let mut buf_ref: Vec<Vec<&mut T>> = vec![];
-
-        for row in self.chars.iter_mut() {
-            let mut row_ref = vec![];
-
-            for character_ref in row {
-                row_ref.push(character_ref)
-            }
-
-            buf_ref.push(row_ref)
-        }
-
-        buf_ref
+    fn read_char(&mut self, row: usize, col: usize) -> Option<ScreenChar> {
+        Some(self.chars.get(row)?.get(col)?.borrow().read())
     }
 }
 
@@ -212,6 +253,17 @@ pub struct Writer<const X: usize, const Y: usize, Buf: BufWrite> {
     pub colour_code: ColourCode,
     /// Whether the colour code is currently locked
     pub lock_colour: bool,
+    /// The text-mode mouse cursor, if it's shown
+    pub mouse_cursor: Option<MouseCursor>,
+}
+
+/// The mouse cursor, drawn by inverting the colours of the cell under it
+#[derive(Clone, Copy, Debug)]
+pub struct MouseCursor {
+    pub row: usize,
+    pub col: usize,
+    /// What the cell looked like before the cursor was drawn over it
+    saved: ScreenChar,
 }
 
 /// ColourCode defaults to 0x0f (background black, foreground white)
@@ -221,6 +273,32 @@ impl Default for ColourCode {
     }
 }
 
+impl ScreenChar {
+    pub fn new(ascii_character: u8, colour_code: ColourCode) -> ScreenChar {
+        ScreenChar {
+            ascii_character,
+            colour_code: colour_code.into(),
+        }
+    }
+
+    pub fn ascii_character(self) -> u8 {
+        self.ascii_character
+    }
+
+    pub fn colour_code(self) -> ColourCode {
+        ColourCode(self.colour_code)
+    }
+
+    /// The same character with its foreground and background colours swapped. The blink bit is
+    /// left clear, since a bright foreground would otherwise make the cell blink.
+    fn inverted(self) -> ScreenChar {
+        ScreenChar {
+            ascii_character: self.ascii_character,
+            colour_code: self.colour_code.rotate_left(4) & 0x7f,
+        }
+    }
+}
+
 impl Default for ScreenChar {
     fn default() -> Self {
         Self {
@@ -238,6 +316,7 @@ impl<'a, const X: usize, const Y: usize> Default for Writer<X, Y, &mut Buffer<X,
             buffer: unsafe { &mut *(0xb8000 as *mut Buffer<X, Y, Volatile<ScreenChar>>) },
             colour_code: Default::default(),
             lock_colour: true,
+            mouse_cursor: None,
         }
     }
 }
@@ -261,16 +340,6 @@ impl ColourCode {
     }
 }
 
-impl Into<[u8; 2]> for ColourCode {
-    fn into(self) -> [u8; 2] {
-        if self.0 > 0x7f {
-            [0x7f, (self.0 - 0x7f)]
-        } else {
-            [self.0, 0]
-        }
-    }
-}
-
 impl Into<u8> for ColourCode {
     fn into(self) -> u8 {
         self.0
@@ -285,26 +354,34 @@ impl Into<ColourCode> for u8 {
 
 impl ColourText {
     pub fn colour(colour_code: ColourCode, text: &str) -> Self {
-        ColourText(colour_code.0, text.into())
+        ColourText {
+            spans: vec![(colour_code, text.into())],
+        }
     }
 
     pub fn text(text: String) -> Self {
-        ColourText(0x0f, text.into())
+        ColourText {
+            spans: vec![(ColourCode::default(), text)],
+        }
+    }
+
+    /// Adds more text to the end, in another colour.
+    pub fn then(mut self, colour_code: ColourCode, text: &str) -> Self {
+        self.spans.push((colour_code, text.into()));
+        self
+    }
+
+    /// The pieces of text, in order, with their colours
+    pub fn spans(&self) -> impl Iterator<Item = (ColourCode, &str)> {
+        self.spans.iter().map(|(colour_code, text)| (*colour_code, text.as_str()))
     }
 }
 
 impl Display for ColourText {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut out = Vec::from([0x00]);
-        let colour_escape: [u8; 2] = ColourCode(self.0).into();
-
-        out.extend(colour_escape);
-        out.extend(self.1.bytes());
-        out.extend([0x00, 0x00, 0x00]);
-
-        let out: String = String::from_utf8_lossy(&out).to_string();
-
-        f.write_str(&out)?;
+        for (_, text) in self.spans() {
+            f.write_str(text)?;
+        }
 
         Ok(())
     }
@@ -312,11 +389,21 @@ impl Display for ColourText {
 
 impl From<&str> for ColourText {
     fn from(value: &str) -> Self {
-        ColourText(0x0f, value.into())
+        ColourText::text(value.into())
     }
 }
 
 impl<const X: usize, const Y: usize, Buf: BufWrite> Writer<X, Y, Buf> {
+    /// The number of columns in use, which the buffer can limit to fewer than `X`
+    pub fn width(&self) -> usize {
+        self.buffer.size().map_or(X, |(columns, _)| columns.min(X))
+    }
+
+    /// The number of rows in use, which the buffer can limit to fewer than `Y`
+    pub fn height(&self) -> usize {
+        self.buffer.size().map_or(Y, |(_, rows)| rows.min(Y))
+    }
+
     /// Writes a character and moves the row and column position forwards to write in the next
     /// available space.
     pub fn write_byte(&mut self, colour_code: ColourCode, byte: u8) {
@@ -329,75 +416,46 @@ impl<const X: usize, const Y: usize, Buf: BufWrite> Writer<X, Y, Buf> {
                 self.buffer.write_char(colour_code, byte, row, col).unwrap_or_else(|_| self.new_line());
                 self.column_position += 1;
 
-                // The column position would only get reset if it has overflowed (by reaching the max that
-                // the ScreenPosition will allow).
-                if self.column_position.0 == 0 {
+                // The column position gets reset if it has overflowed (by reaching the max that the
+                // ScreenPosition will allow), but the buffer might be using fewer columns than that.
+                if self.column_position.0 == 0 || self.column_position.0 >= self.width() {
                     self.new_line()
                 }
             }
         }
     }
 
-    /// Write a ColourText to the VGA text buffer.
-    pub fn write_colourful(&mut self, s: ColourText) {
-        let prev = self.colour_code;
-        let mut bytes = s.1.bytes();
-
-        // If the colour is locked, don't change it.
-        if !self.lock_colour {
-            self.colour_code = s.0.into()
-        }
-
-        while let Some(byte) = bytes.next() {
-            match byte {
-                0x00 => match [bytes.next(), bytes.next()] {
-                    [Some(byte_1), Some(byte_2)] => {
-                        if !self.lock_colour {
-                            self.colour_code = ColourCode(byte_1 + byte_2)
-                        }
-                    }
-                    [Some(byte_1), None] => {
-                        if !self.lock_colour {
-                            self.write_byte(self.colour_code, byte_1)
-                        }
-                    }
-                    _ => self.write_byte(self.colour_code, 0x00),
-                },
-                // Printable ASCII range
-                0x20..=0x7e | b'\n' => self.write_byte(self.colour_code, byte),
-                // If a character is outside the printable ASCII range (i.e DEL, ESC),
-                // write a square character in its place to indicate this.
-                _ => self.write_byte(self.colour_code, 0xfe),
+    /// Writes a character, like `write_byte`. Anything outside of printable ASCII is shown with
+    /// the glyph that the current font maps it to (see `font`), which is a square if there
+    /// isn't one (as for control characters, like DEL and ESC).
+    pub fn write_character(&mut self, colour_code: ColourCode, character: char) {
+        match character {
+            '\n' => self.new_line(),
+            // Printable ASCII range
+            ' '..='~' => self.write_byte(colour_code, character as u8),
+            character => self.write_byte(colour_code, font::glyph(character)),
+        }
+    }
+
+    /// Write a ColourText to the VGA text buffer, in its own colours unless the writer's colour
+    /// is locked.
+    pub fn write_colourful(&mut self, text: &ColourText) {
+        for (colour_code, text) in text.spans() {
+            // If the colour is locked, don't change it.
+            let colour_code = if self.lock_colour { self.colour_code } else { colour_code };
+
+            for character in text.chars() {
+                self.write_character(colour_code, character);
             }
         }
 
-        self.colour_code = prev;
+        self.draw_mouse_cursor();
+        self.sync_cursor();
     }
 
     /// Same as self.write_colourful(), but it converts `s` into a `ColourText` struct
     pub fn write_string(&mut self, s: &str) {
-        self.write_colourful(s.into())
-    }
-
-    /// Returns a Writer that can write only within a certain rectangle
-    pub fn within_rect<'a, const WIDTH: usize, const HEIGHT: usize>(&'a mut self, offset_x: usize, offset_y: usize) -> Writer<WIDTH, HEIGHT, Buffer<WIDTH, HEIGHT, &'a mut Buf::Character>>
-    where &'a mut <Buf as BufWrite>::Character: BorrowMut<Volatile<ScreenChar>>
-    {
-        let mut char_buf = self.buffer.char_buf();
-
-        let buffer_ref: [[&mut Buf::Character; WIDTH]; HEIGHT] = array::from_fn(|row| 
-            array::from_fn(
-                |_|  char_buf.get_mut(row + offset_y).unwrap().remove(offset_x)
-            )
-        );
-
-        return Writer {
-            column_position: ScreenPosition(0),
-            row_position: ScreenPosition(0),
-            buffer: Buffer { chars: buffer_ref },
-            colour_code: ColourCode::default(),
-            lock_colour: false
-        }
+        self.write_colourful(&s.into())
     }
 
     /// Draws a rectangle with the specific character, height, width, and X, Y offset.
@@ -421,22 +479,49 @@ impl<const X: usize, const Y: usize, Buf: BufWrite> Writer<X, Y, Buf> {
         }
     }
 
-    /// Draws a newline.
+    /// Draws a newline. On the last row, everything moves up a row instead, discarding the
+    /// topmost line.
     pub fn new_line(&mut self) {
-        self.row_position += 1;
         self.column_position = ScreenPosition(0);
 
-        // The only time that the row_position would be 0 is if the ScreenPosition has overflown its bounds.
-        // This means that we've run out of space, and need to clear the buffer.
-        // TODO: Move the rest of the text upwards instead of clearing the buffer, discarding the topmost line.
-        if self.row_position == ScreenPosition(0) {
-            self.clear_all();
+        if self.row_position.0 + 1 < self.height() {
+            self.row_position += 1;
+            return;
         }
+
+        // The mouse cursor stays where it is, rather than scrolling up with the text
+        let mouse_cursor = self.mouse_cursor.map(|cursor| (cursor.row, cursor.col));
+        self.set_mouse_cursor(None);
+
+        let blank = self.blank();
+        self.buffer.scroll_up(blank);
+
+        self.set_mouse_cursor(mouse_cursor);
+    }
+
+    /// Moves back one character and blanks it out, wrapping to the end of the previous row.
+    pub fn backspace(&mut self) {
+        match (self.row_position.0, self.column_position.0) {
+            (0, 0) => return,
+            (row, 0) => {
+                self.row_position = ScreenPosition(row - 1);
+                self.column_position = ScreenPosition(self.width() - 1);
+            }
+            (_, col) => self.column_position = ScreenPosition(col - 1),
+        }
+
+        let blank = self.blank();
+
+        self.buffer
+            .write_char(ColourCode(blank.colour_code), blank.ascii_character, self.row_position.0, self.column_position.0)
+            .ok();
+        self.draw_mouse_cursor();
+        self.sync_cursor();
     }
 
     /// Clears the specific row and replaces it with another character
     pub fn clear_row(&mut self, row: usize, screen_char: ScreenChar) {
-        for col in 0..X {
+        for col in 0..self.width() {
             self.buffer.write_char(ColourCode(screen_char.colour_code), screen_char.ascii_character, row, col).unwrap_or_else(|_| self.new_line());
         }
     }
@@ -448,9 +533,72 @@ impl<const X: usize, const Y: usize, Buf: BufWrite> Writer<X, Y, Buf> {
 
         let blank = self.blank();
 
-        for y in 0..Y {
+        for y in 0..self.height() {
             self.clear_row(y, blank)
         }
+
+        self.draw_mouse_cursor();
+        self.sync_cursor();
+    }
+
+    /// Moves the writing position, and the blinking cursor with it.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row_position = ScreenPosition(row.min(self.height() - 1));
+        self.column_position = ScreenPosition(col.min(self.width() - 1));
+        self.sync_cursor();
+    }
+
+    /// The shape of the blinking cursor, or `None` if it's hidden
+    pub fn cursor_shape(&self) -> Option<CursorShape> {
+        self.buffer.cursor_shape()
+    }
+
+    /// Shows the blinking cursor in the given shape, or hides it.
+    pub fn set_cursor_shape(&mut self, shape: Option<CursorShape>) {
+        self.buffer.set_cursor_shape(shape);
+    }
+
+    /// Moves the blinking cursor to the writing position.
+    fn sync_cursor(&mut self) {
+        self.buffer.move_cursor(self.row_position.0, self.column_position.0);
+    }
+
+    /// Moves the mouse cursor to a cell, or hides it.
+    pub fn set_mouse_cursor(&mut self, position: Option<(usize, usize)>) {
+        if let Some(cursor) = self.mouse_cursor.take() {
+            // Only restore the cell if nothing has been written over the cursor since
+            if self.buffer.read_char(cursor.row, cursor.col) == Some(cursor.saved.inverted()) {
+                self.put_char(cursor.row, cursor.col, cursor.saved);
+            }
+        }
+
+        if let Some((row, col)) = position {
+            self.mouse_cursor = self.buffer.read_char(row, col).map(|saved| MouseCursor { row, col, saved });
+            self.draw_mouse_cursor();
+        }
+    }
+
+    /// Draws the mouse cursor again if text was written over it. Anything that writes to the
+    /// buffer directly should call this afterwards.
+    pub fn draw_mouse_cursor(&mut self) {
+        let Some(mut cursor) = self.mouse_cursor else {
+            return;
+        };
+
+        match self.buffer.read_char(cursor.row, cursor.col) {
+            Some(current) if current == cursor.saved.inverted() => return,
+            Some(current) => cursor.saved = current,
+            None => return,
+        }
+
+        self.put_char(cursor.row, cursor.col, cursor.saved.inverted());
+        self.mouse_cursor = Some(cursor);
+    }
+
+    fn put_char(&mut self, row: usize, col: usize, screen_char: ScreenChar) {
+        self.buffer
+            .write_char(ColourCode(screen_char.colour_code), screen_char.ascii_character, row, col)
+            .ok();
     }
 
     /// Returns a space character with the Writer's current colour code.
@@ -462,9 +610,38 @@ impl<const X: usize, const Y: usize, Buf: BufWrite> Writer<X, Y, Buf> {
     }
 }
 
+impl<const X: usize, const Y: usize, T: BorrowMut<Volatile<ScreenChar>>> Writer<X, Y, Buffer<X, Y, T>> {
+    /// Returns a Writer that can write only within a certain rectangle. Only plain buffers have
+    /// this, since it writes to the characters directly; a terminal's buffer wouldn't see it.
+    /// Panics if the rectangle doesn't fit.
+    pub fn within_rect<const WIDTH: usize, const HEIGHT: usize>(&mut self, offset_x: usize, offset_y: usize) -> Writer<WIDTH, HEIGHT, Buffer<WIDTH, HEIGHT, &mut Volatile<ScreenChar>>> {
+        let mut rows = self.buffer.chars.iter_mut().skip(offset_y);
+
+        let chars: [[&mut Volatile<ScreenChar>; WIDTH]; HEIGHT] = array::from_fn(|_| {
+            let mut characters = rows.next().expect("The rectangle is too tall").iter_mut().skip(offset_x);
+
+            array::from_fn(|_| characters.next().expect("The rectangle is too wide").borrow_mut())
+        });
+
+        Writer {
+            column_position: ScreenPosition(0),
+            row_position: ScreenPosition(0),
+            buffer: Buffer { chars },
+            colour_code: ColourCode::default(),
+            lock_colour: false,
+            mouse_cursor: None
+        }
+    }
+}
+
 impl<const X: usize, const Y: usize, Buf: BufWrite> fmt::Write for Writer<X, Y, Buf> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_colourful(ColourText::colour(self.colour_code, s));
+        for character in s.chars() {
+            self.write_character(self.colour_code, character);
+        }
+
+        self.draw_mouse_cursor();
+        self.sync_cursor();
 
         Ok(())
     }
@@ -472,6 +649,15 @@ impl<const X: usize, const Y: usize, Buf: BufWrite> fmt::Write for Writer<X, Y,
 
 pub struct PotentialWriter<'a, T: Write>(Option<MutexGuard<'a, T>>);
 
+impl<const X: usize, const Y: usize, Buf: BufWrite> PotentialWriter<'_, Writer<X, Y, Buf>> {
+    /// Same as `Writer::write_colourful`, if the writer could be locked.
+    pub fn write_colourful(&mut self, text: &ColourText) {
+        if let Some(writer) = &mut self.0 {
+            writer.write_colourful(text);
+        }
+    }
+}
+
 impl<T: Write> fmt::Write for PotentialWriter<'_, T> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         if let Some(writer) = &mut self.0 {
@@ -482,27 +668,98 @@ impl<T: Write> fmt::Write for PotentialWriter<'_, T> {
     }
 }
 
+/// The shape of the blinking cursor, as the first and last scanlines it covers in a character
+/// cell. VGA text mode fonts are 16 scanlines tall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorShape {
+    pub start: u8,
+    pub end: u8,
+}
+
+impl CursorShape {
+    pub const UNDERLINE: CursorShape = CursorShape { start: 13, end: 14 };
+    pub const HALF_BLOCK: CursorShape = CursorShape { start: 8, end: 15 };
+    pub const BLOCK: CursorShape = CursorShape { start: 0, end: 15 };
+}
+
+impl Default for CursorShape {
+    fn default() -> Self {
+        CursorShape::UNDERLINE
+    }
+}
+
+/// The blinking cursor, programmed through the CRT controller's registers.
+pub mod hardware_cursor {
+    use x86_64::instructions::port::Port;
+
+    use super::{CursorShape, TEXT_WIDTH};
+
+    const CRTC_INDEX: u16 = 0x3d4;
+    const CRTC_DATA: u16 = 0x3d5;
+
+    const CURSOR_START: u8 = 0x0a;
+    const CURSOR_END: u8 = 0x0b;
+    const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+    const CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+    /// Set in the cursor start register to hide the cursor
+    const CURSOR_DISABLE: u8 = 1 << 5;
+
+    fn write(register: u8, value: u8) {
+        unsafe {
+            Port::<u8>::new(CRTC_INDEX).write(register);
+            Port::<u8>::new(CRTC_DATA).write(value);
+        }
+    }
+
+    fn read(register: u8) -> u8 {
+        unsafe {
+            Port::<u8>::new(CRTC_INDEX).write(register);
+            Port::<u8>::new(CRTC_DATA).read()
+        }
+    }
+
+    pub fn move_to(row: usize, col: usize) {
+        let position = (row * TEXT_WIDTH + col) as u16;
+
+        write(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+        write(CURSOR_LOCATION_LOW, position as u8);
+    }
+
+    /// Shows the cursor in the given shape, or hides it.
+    pub fn set_shape(shape: Option<CursorShape>) {
+        // The upper bits of both registers mean other things, so they're left alone
+        let start = read(CURSOR_START) & 0b1100_0000;
+        let end = read(CURSOR_END) & 0b1110_0000;
+
+        match shape {
+            Some(shape) => {
+                write(CURSOR_START, start | (shape.start & 0x1f));
+                write(CURSOR_END, end | (shape.end & 0x1f));
+            }
+            None => write(CURSOR_START, start | CURSOR_DISABLE),
+        }
+    }
+}
+
+/// The writer of the current task's virtual terminal. Code outside of tasks (during boot, or
+/// in interrupt handlers that interrupt an idle CPU) writes to the first one.
 pub mod global_writer {
-    type ScreenWriter = Writer<BUFFER_WIDTH, BUFFER_HEIGHT, &'static mut Buffer<BUFFER_WIDTH, BUFFER_HEIGHT, Volatile<ScreenChar>>>;
+    type ScreenWriter = VtWriter;
 
-    use super::Buffer;
     use super::ColourCode;
     use super::PotentialWriter;
-    use super::ScreenChar;
-    use super::Writer;
-    use super::WRITER;
-    use super::{BUFFER_HEIGHT, BUFFER_WIDTH};
+    use crate::vt::{self, VtWriter};
     use spin::MutexGuard;
-    use volatile::Volatile;
 
     /// Acquires the global writer.
     pub fn lock<'a>() -> MutexGuard<'a, ScreenWriter> {
-        WRITER.lock()
+        vt::lock(vt::current())
     }
 
     /// Sets the colour of the global writer.
     pub fn set_colour(colour: ColourCode) -> Result<(), &'static str> {
-        let Some(mut writer) = WRITER.try_lock() else {
+        let Some(mut writer) = try_lock() else {
             return Err("Failed to lock writer to set colour.")
         };
 
@@ -516,7 +773,7 @@ pub mod global_writer {
 
     /// Changes that status of the colour lock of the global writer.
     pub fn lock_colour(set_to: bool) -> Result<(), ()> {
-        match WRITER.try_lock() {
+        match try_lock() {
             Some(mut writer) => {
                 writer.lock_colour = set_to;
                 Ok(())
@@ -529,7 +786,7 @@ pub mod global_writer {
     /// fmt::Write. If the PotentialWriter is none, then it won't write to the VGA
     /// output buffer.
     pub fn maybe<'a>() -> PotentialWriter<'a, ScreenWriter> {
-        let Some(writer) = WRITER.try_lock() else {
+        let Some(writer) = try_lock() else {
             return PotentialWriter(None);
         };
 
@@ -539,15 +796,14 @@ pub mod global_writer {
     /// Attempts to lock the writer. Preferable to a writer::lock because it
     /// evades deadlocks.
     pub fn try_lock<'a>() -> Option<MutexGuard<'a, ScreenWriter>> {
-        WRITER.try_lock()
+        vt::try_lock(vt::current())
     }
 
-    /// Forcefully unlocks the writer and then locks the now-free writer.
+    /// Forcefully unlocks the writer of the terminal on screen, and then locks it.
     /// This is unsafe because it might unlock the Mutex while it's still in use.
     pub unsafe fn force_lock<'a>() -> MutexGuard<'a, ScreenWriter> {
         // SAFETY: Might unlock the Writer while it's being used, causing undefined
         // behaviour
-        WRITER.force_unlock();
-        WRITER.lock()
+        vt::force_lock_active()
     }
 }
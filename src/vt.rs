@@ -0,0 +1,323 @@
+//! Virtual terminals. Each one has an off-screen copy of the text buffer, its own cursor,
+//! scrollback and input focus. Only the active terminal is shown; Alt+F1 to Alt+F6 switch
+//! between them, and Shift+Page Up and Shift+Page Down scroll back through the active one.
+//!
+//! Tasks write to the terminal they were spawned on (see `runtime::Builder::console`), through
+//! `global_writer`. The active terminal is shown in the VGA text buffer, or on the framebuffer
+//! console with the `framebuffer` feature.
+
+use core::array;
+use core::borrow::BorrowMut;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::VecDeque;
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+use volatile::Volatile;
+
+#[cfg(feature = "framebuffer")]
+use crate::framebuffer;
+use crate::runtime;
+use crate::vga_buffer::{
+    hardware_buffer, hardware_cursor, screen_size, BufWrite, ColourCode, CursorShape, ScreenChar, ScreenPosition, Writer, BUFFER_HEIGHT, BUFFER_WIDTH,
+};
+
+/// The number of virtual terminals, one for each of F1 to F6
+pub const VT_COUNT: usize = 6;
+/// How many lines each terminal keeps after they scroll off the top
+const SCROLLBACK_LINES: usize = 500;
+/// The heap every terminal's scrollback takes up once it's full
+pub const SCROLLBACK_SIZE: usize = VT_COUNT * SCROLLBACK_LINES * mem::size_of::<[ScreenChar; BUFFER_WIDTH]>();
+
+pub type VtWriter = Writer<BUFFER_WIDTH, BUFFER_HEIGHT, VtBuffer>;
+
+lazy_static! {
+    static ref TERMINALS: [Mutex<VtWriter>; VT_COUNT] = array::from_fn(|vt| {
+        Mutex::new(Writer {
+            column_position: ScreenPosition(0),
+            row_position: ScreenPosition(0),
+            buffer: VtBuffer::new(vt == 0),
+            colour_code: ColourCode::default(),
+            lock_colour: false,
+            mouse_cursor: None,
+        })
+    });
+}
+
+/// The terminal on screen
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+type Row = [Volatile<ScreenChar>; BUFFER_WIDTH];
+
+/// An off-screen text buffer. Writes to the active terminal's buffer are copied to the screen
+/// as they happen, unless it's scrolled back.
+pub struct VtBuffer {
+    rows: [Row; BUFFER_HEIGHT],
+    /// Lines that have scrolled off the top, oldest first
+    scrollback: VecDeque<[ScreenChar; BUFFER_WIDTH]>,
+    /// How many lines back the view is scrolled; 0 shows the live buffer
+    view_offset: usize,
+    active: bool,
+    cursor: (usize, usize),
+    cursor_shape: Option<CursorShape>,
+}
+
+impl VtBuffer {
+    fn new(active: bool) -> VtBuffer {
+        VtBuffer {
+            rows: array::from_fn(|_| array::from_fn(|_| Volatile::new(ScreenChar::default()))),
+            scrollback: VecDeque::new(),
+            view_offset: 0,
+            active,
+            cursor: (0, 0),
+            cursor_shape: Some(CursorShape::default()),
+        }
+    }
+
+    /// Whether changes should be copied to the screen straight away
+    fn shown_live(&self) -> bool {
+        self.active && self.view_offset == 0
+    }
+
+    /// Puts the blinking cursor where this terminal wants it. It's hidden while the terminal is
+    /// scrolled back, since the line it's on isn't in view.
+    fn render_cursor(&self) {
+        let (row, col) = self.cursor;
+
+        show_cursor(row, col);
+        show_cursor_shape(self.cursor_shape.filter(|_| self.view_offset == 0));
+    }
+
+    /// Copies the part of the terminal that's in view to the screen, along with the cursor.
+    fn render(&self) {
+        let first_line = self.scrollback.len() - self.view_offset;
+        let (columns, rows) = screen_size();
+
+        for row in 0..rows {
+            let line = first_line + row;
+
+            for col in 0..columns {
+                let character = match line.checked_sub(self.scrollback.len()) {
+                    None => self.scrollback[line][col],
+                    Some(row) => self.rows[row][col].read(),
+                };
+
+                // SAFETY: only the active terminal renders, and its lock is held
+                unsafe { show(character.colour_code(), character.ascii_character(), row, col) }.ok();
+            }
+        }
+
+        self.render_cursor();
+    }
+
+    /// Scrolls the view back by `lines`, or forwards if it's negative.
+    fn scroll_view(&mut self, lines: isize) {
+        let offset = self.view_offset.saturating_add_signed(lines).min(self.scrollback.len());
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.render();
+        }
+    }
+}
+
+impl BufWrite for VtBuffer {
+    fn write_char(&mut self, colour_code: ColourCode, ascii_code: u8, row: usize, col: usize) -> fmt::Result {
+        if ascii_code == b'\n' {
+            return Err(fmt::Error);
+        }
+
+        let character = self.rows.get_mut(row).and_then(|row| row.get_mut(col)).ok_or(fmt::Error)?;
+        character.write(ScreenChar::new(ascii_code, colour_code));
+
+        if self.shown_live() {
+            // SAFETY: only the active terminal writes to the screen, and its lock is held
+            unsafe { show(colour_code, ascii_code, row, col) }?;
+        }
+
+        Ok(())
+    }
+
+    fn clear_screen(&mut self, colour: ColourCode) {
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                self.write_char(colour, b' ', row, col).ok();
+            }
+        }
+    }
+
+    fn read_char(&mut self, row: usize, col: usize) -> Option<ScreenChar> {
+        Some(self.rows.get(row)?.get(col)?.read())
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        Some(screen_size())
+    }
+
+    fn move_cursor(&mut self, row: usize, col: usize) {
+        if self.cursor != (row, col) {
+            self.cursor = (row, col);
+
+            if self.shown_live() {
+                show_cursor(row, col);
+            }
+        }
+    }
+
+    fn cursor_shape(&self) -> Option<CursorShape> {
+        self.cursor_shape
+    }
+
+    fn set_cursor_shape(&mut self, shape: Option<CursorShape>) {
+        self.cursor_shape = shape;
+
+        if self.shown_live() {
+            self.render_cursor();
+        }
+    }
+
+    fn scroll_up(&mut self, blank: ScreenChar) {
+        // Claimed in one go, since growing it bit by bit would briefly need twice the memory
+        if self.scrollback.capacity() == 0 {
+            self.scrollback.reserve_exact(SCROLLBACK_LINES);
+        }
+
+        if self.scrollback.len() == SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(self.rows[0].each_ref().map(|character| character.read()));
+
+        if self.view_offset > 0 {
+            // Keep the view on the same lines while output carries on underneath
+            self.view_offset = (self.view_offset + 1).min(self.scrollback.len());
+        }
+
+        // Only the rows on screen move, so that text mode scrolls at its own bottom row
+        let (_, rows) = screen_size();
+        self.rows[..rows].rotate_left(1);
+        for character in &mut self.rows[rows - 1] {
+            character.borrow_mut().write(blank);
+        }
+
+        if self.shown_live() {
+            self.render();
+        }
+    }
+}
+
+/// Draws a character on the screen, which is the framebuffer console if it's running. Characters
+/// that don't fit are left out.
+///
+/// # Safety
+/// Only the active terminal may draw, with its lock held.
+unsafe fn show(colour_code: ColourCode, ascii_code: u8, row: usize, col: usize) -> fmt::Result {
+    #[cfg(feature = "framebuffer")]
+    if let Some(console) = framebuffer::console() {
+        return console.write_char(colour_code, ascii_code, row, col);
+    }
+
+    hardware_buffer().write_char(colour_code, ascii_code, row, col).ok();
+    Ok(())
+}
+
+fn show_cursor(row: usize, col: usize) {
+    #[cfg(feature = "framebuffer")]
+    // SAFETY: only the active terminal moves the cursor, with its lock held
+    if let Some(console) = unsafe { framebuffer::console() } {
+        return console.move_cursor(row, col);
+    }
+
+    hardware_cursor::move_to(row, col);
+}
+
+fn show_cursor_shape(shape: Option<CursorShape>) {
+    #[cfg(feature = "framebuffer")]
+    // SAFETY: only the active terminal changes the cursor, with its lock held
+    if let Some(console) = unsafe { framebuffer::console() } {
+        return console.set_cursor_shape(shape);
+    }
+
+    hardware_cursor::set_shape(shape);
+}
+
+/// Sets up the terminals and clears the screen. Called early, since the terminals are built on
+/// the stack of whatever uses them first.
+pub fn init() {
+    lazy_static::initialize(&TERMINALS);
+
+    TERMINALS[active()].lock().buffer.render();
+}
+
+/// The terminal of the task that's running
+pub fn current() -> usize {
+    runtime::current_console().min(VT_COUNT - 1)
+}
+
+/// The terminal on screen
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+pub fn lock(vt: usize) -> MutexGuard<'static, VtWriter> {
+    TERMINALS[vt].lock()
+}
+
+pub fn try_lock(vt: usize) -> Option<MutexGuard<'static, VtWriter>> {
+    TERMINALS[vt].try_lock()
+}
+
+/// Forcefully unlocks the active terminal, and then locks it.
+///
+/// # Safety
+/// Whatever held the lock might still be using the terminal.
+pub unsafe fn force_lock_active() -> MutexGuard<'static, VtWriter> {
+    let terminal = &TERMINALS[active()];
+
+    terminal.force_unlock();
+    terminal.lock()
+}
+
+/// Draws the active terminal again, after the screen has been changed behind its back.
+pub fn redraw() {
+    TERMINALS[active()].lock().buffer.render();
+}
+
+/// Puts a terminal on screen. The mouse cursor moves over to it.
+pub fn switch(vt: usize) {
+    if vt >= VT_COUNT {
+        return;
+    }
+
+    let mut old = TERMINALS[active()].lock();
+    if active() == vt {
+        return;
+    }
+
+    let mouse_cursor = old.mouse_cursor.map(|cursor| (cursor.row, cursor.col));
+    old.set_mouse_cursor(None);
+    old.buffer.active = false;
+
+    // Hold on to the old terminal until the new one is on screen, so that nothing can switch
+    // in between
+    let mut new = TERMINALS[vt].lock();
+    new.buffer.active = true;
+    new.buffer.render();
+    new.set_mouse_cursor(mouse_cursor);
+
+    ACTIVE.store(vt, Ordering::Release);
+}
+
+/// Scrolls the active terminal's view back by `lines`, or forwards if it's negative.
+pub fn scroll(lines: isize) {
+    TERMINALS[active()].lock().buffer.scroll_view(lines);
+}
+
+/// Scrolls the active terminal back down to its live output.
+pub fn scroll_to_bottom() {
+    let mut terminal = TERMINALS[active()].lock();
+    let offset = terminal.buffer.view_offset;
+
+    terminal.buffer.scroll_view(-(offset as isize));
+}